@@ -0,0 +1,388 @@
+extern crate alloc;
+
+use core::cmp::Ordering;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, Context, Result};
+
+// Boot Loader Specification type #1 entries with systemd's boot counting
+// (https://systemd.io/AUTOMATIC_BOOT_ASSESSMENT/). An entry named
+// `foo+3.conf` has 3 tries left; each attempt renames it to `foo+2-1.conf`,
+// `foo+1-2.conf` and so on. The OS renames it to `foo.conf` once the boot is
+// deemed good. Entries with 0 tries left are "bad".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootCounter {
+    pub left: u32,
+    pub done: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BlsEntry {
+    // file name without the counter and `.conf`
+    pub id: String,
+    // file name as found on disk
+    pub file_name: String,
+    pub counter: Option<BootCounter>,
+    pub title: Option<String>,
+    pub version: Option<String>,
+    pub sort_key: Option<String>,
+    pub linux: Option<String>,
+    pub initrd: Vec<String>,
+    pub efi: Option<String>,
+    pub options: Vec<String>,
+}
+
+// Split `foo+3-1.conf` into `foo` and its counter
+pub fn parse_file_name(file_name: &str) -> Option<(String, Option<BootCounter>)> {
+    let stem = file_name.strip_suffix(".conf")?;
+    let Some((id, counter)) = stem.rsplit_once('+') else {
+        return Some((stem.to_string(), None));
+    };
+    let (left, done) = match counter.split_once('-') {
+        Some((left, done)) => (left, Some(done)),
+        None => (counter, None),
+    };
+    let Ok(left) = left.parse::<u32>() else {
+        // a '+' that isn't a counter is part of the name
+        return Some((stem.to_string(), None));
+    };
+    let done = match done.map(|d| d.parse::<u32>()) {
+        Some(Ok(done)) => Some(done),
+        Some(Err(_)) => return Some((stem.to_string(), None)),
+        None => None,
+    };
+    Some((id.to_string(), Some(BootCounter { left, done })))
+}
+
+// Compare versions the way humans expect: digit runs numerically
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (a_num, b_num) = (&a[..a_len], &b[..b_len]);
+                // strip leading zeros, then longer number is bigger
+                let a_num = &a_num[a_num.iter().take_while(|c| **c == b'0').count()..];
+                let b_num = &b_num[b_num.iter().take_while(|c| **c == b'0').count()..];
+                let ord = a_num.len().cmp(&b_num.len()).then(a_num.cmp(b_num));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+impl BlsEntry {
+    pub fn parse(file_name: &str, text: &str) -> Result<Self> {
+        let (id, counter) = parse_file_name(file_name)
+            .with_context(|| format!("'{}' is not a .conf file", file_name))?;
+        let mut entry = BlsEntry {
+            id,
+            file_name: file_name.to_string(),
+            counter,
+            ..Default::default()
+        };
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once(char::is_whitespace) {
+                Some((key, value)) => (key, value.trim().to_string()),
+                None => (line, String::new()),
+            };
+            match key {
+                "title" => entry.title = Some(value),
+                "version" => entry.version = Some(value),
+                "sort-key" => entry.sort_key = Some(value),
+                "linux" => entry.linux = Some(value),
+                "initrd" => entry.initrd.push(value),
+                "efi" => entry.efi = Some(value),
+                "options" => entry.options.push(value),
+                // machine-id, devicetree, architecture... are of no use to us
+                _ => {}
+            }
+        }
+        if entry.linux.is_none() && entry.efi.is_none() {
+            return Err(anyhow!(
+                "entry '{}' has neither 'linux' nor 'efi'",
+                file_name
+            ));
+        }
+        Ok(entry)
+    }
+
+    pub fn is_bad(&self) -> bool {
+        self.counter.is_some_and(|c| c.left == 0)
+    }
+
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.id)
+    }
+
+    // Image to load: a unified kernel/EFI binary or a kernel with EFI stub.
+    // Entries use `/`, firmware file systems only know `\`
+    pub fn image_path(&self) -> String {
+        self.efi
+            .as_deref()
+            .or(self.linux.as_deref())
+            .unwrap_or_default()
+            .replace('/', "\\")
+    }
+
+    // Command line for the image. The kernel's EFI stub loads `initrd=` itself
+    pub fn load_options(&self) -> String {
+        let mut words: Vec<String> = Vec::new();
+        if self.efi.is_none() {
+            words.extend(self.initrd.iter().map(|i| format!("initrd={}", i)));
+        }
+        words.extend(self.options.iter().cloned());
+        words.join(" ")
+    }
+
+    // File name after one more boot attempt, None if the entry isn't counted
+    pub fn decremented_file_name(&self) -> Option<String> {
+        let counter = self.counter?;
+        if counter.left == 0 {
+            return None;
+        }
+        Some(format!(
+            "{}+{}-{}.conf",
+            self.id,
+            counter.left - 1,
+            counter.done.unwrap_or(0) + 1
+        ))
+    }
+}
+
+// Bad entries last, then entries with a sort-key by sort-key, then newest
+// version first with unversioned entries after versioned ones, then by id
+// in reverse. Every step is a total order, so the whole is one too
+fn compare_entries(a: &BlsEntry, b: &BlsEntry) -> Ordering {
+    a.is_bad()
+        .cmp(&b.is_bad())
+        .then_with(|| match (&a.sort_key, &b.sort_key) {
+            (Some(x), Some(y)) => x.cmp(y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        })
+        .then_with(|| match (&a.version, &b.version) {
+            (Some(x), Some(y)) => compare_versions(y, x),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        })
+        .then_with(|| compare_versions(&b.id, &a.id))
+}
+
+impl AsRef<BlsEntry> for BlsEntry {
+    fn as_ref(&self) -> &BlsEntry {
+        self
+    }
+}
+
+// Entries can be sorted together with where they were found, e.g. the
+// partition, as long as they give access to the entry
+pub fn sort_entries<T: AsRef<BlsEntry>>(entries: &mut [T]) {
+    entries.sort_by(|a, b| compare_entries(a.as_ref(), b.as_ref()));
+}
+
+// Entries in boot order. Bad entries are only offered when nothing else is left
+pub fn bootable_entries<T: AsRef<BlsEntry>>(entries: &[T]) -> impl Iterator<Item = &T> {
+    let any_good = entries.iter().any(|e| !e.as_ref().is_bad());
+    entries
+        .iter()
+        .filter(move |e| !(any_good && e.as_ref().is_bad()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file_name: &str, text: &str) -> BlsEntry {
+        BlsEntry::parse(file_name, text).unwrap()
+    }
+
+    fn ids(entries: &[BlsEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.file_name.as_str()).collect()
+    }
+
+    #[test]
+    fn file_names() {
+        let counter = |left, done| Some(BootCounter { left, done });
+        assert_eq!(
+            parse_file_name("fedora.conf"),
+            Some(("fedora".to_string(), None))
+        );
+        assert_eq!(
+            parse_file_name("fedora+3.conf"),
+            Some(("fedora".to_string(), counter(3, None)))
+        );
+        assert_eq!(
+            parse_file_name("fedora+1-2.conf"),
+            Some(("fedora".to_string(), counter(1, Some(2))))
+        );
+        // a '+' that isn't followed by a counter is part of the id
+        assert_eq!(
+            parse_file_name("6.8.0+rt.conf"),
+            Some(("6.8.0+rt".to_string(), None))
+        );
+        assert_eq!(
+            parse_file_name("a+1-x.conf"),
+            Some(("a+1-x".to_string(), None))
+        );
+        assert_eq!(parse_file_name("fedora+3"), None);
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(compare_versions("6.8.0", "6.8.0"), Ordering::Equal);
+        assert_eq!(compare_versions("6.10", "6.9"), Ordering::Greater);
+        assert_eq!(compare_versions("6.8.0-31", "6.8.0-4"), Ordering::Greater);
+        assert_eq!(compare_versions("007", "7"), Ordering::Equal);
+        assert_eq!(compare_versions("6.8", "6.8.1"), Ordering::Less);
+        assert_eq!(compare_versions("6.8a", "6.8b"), Ordering::Less);
+        assert_eq!(compare_versions("", "1"), Ordering::Less);
+    }
+
+    #[test]
+    fn parse_entry() {
+        let e = entry(
+            "ubuntu-6.8+2-1.conf",
+            "# comment\ntitle  Ubuntu 24.04\nversion 6.8.0\nlinux /vmlinuz-6.8\n\
+             initrd /initrd-6.8\ninitrd /microcode\noptions root=LABEL=root\n\
+             options quiet\nmachine-id 0123\n",
+        );
+        assert_eq!(e.id, "ubuntu-6.8");
+        assert_eq!(e.title(), "Ubuntu 24.04");
+        assert_eq!(e.image_path(), "\\vmlinuz-6.8");
+        assert_eq!(
+            e.load_options(),
+            "initrd=/initrd-6.8 initrd=/microcode root=LABEL=root quiet"
+        );
+
+        // a unified kernel loads its own initrd
+        let e = entry("uki.conf", "efi /EFI/Linux/uki.efi\ninitrd /x\n");
+        assert_eq!(e.title(), "uki");
+        assert_eq!(e.image_path(), "\\EFI\\Linux\\uki.efi");
+        assert_eq!(e.load_options(), "");
+
+        assert!(BlsEntry::parse("empty.conf", "title nothing to boot\n").is_err());
+    }
+
+    #[test]
+    fn decremented_file_names() {
+        let name = |file_name| entry(file_name, "linux /k").decremented_file_name();
+        assert_eq!(name("a+3.conf").as_deref(), Some("a+2-1.conf"));
+        assert_eq!(name("a+1-2.conf").as_deref(), Some("a+0-3.conf"));
+        assert_eq!(name("a+0-3.conf"), None);
+        assert_eq!(name("a.conf"), None);
+    }
+
+    #[test]
+    fn sorting() {
+        let mut entries = vec![
+            entry("old.conf", "linux /k\nversion 6.1"),
+            entry("bad+0-3.conf", "linux /k\nversion 6.9\nsort-key a"),
+            entry("new.conf", "linux /k\nversion 6.10"),
+            entry("keyed.conf", "linux /k\nversion 5.0\nsort-key fedora"),
+            entry("keyed-new.conf", "linux /k\nversion 5.4\nsort-key fedora"),
+            entry("unversioned-2.conf", "linux /k"),
+            entry("unversioned-10.conf", "linux /k"),
+        ];
+        sort_entries(&mut entries);
+        // entries with a sort-key first, then newest version, then the
+        // unversioned ones by id in reverse
+        assert_eq!(
+            ids(&entries),
+            [
+                "keyed-new.conf",
+                "keyed.conf",
+                "new.conf",
+                "old.conf",
+                "unversioned-10.conf",
+                "unversioned-2.conf",
+                "bad+0-3.conf",
+            ]
+        );
+        assert_eq!(bootable_entries(&entries).count(), 6);
+    }
+
+    #[test]
+    fn order_is_total() {
+        // a cycle when versions only counted if both entries had one:
+        // c < a by version, a < b and b < c by id
+        let entries = [
+            entry(
+                "z.conf",
+                "linux /k
+version 1",
+            ),
+            entry("m.conf", "linux /k"),
+            entry(
+                "a.conf",
+                "linux /k
+version 2",
+            ),
+        ];
+        for (x, y, z) in [
+            (0, 1, 2),
+            (0, 2, 1),
+            (1, 0, 2),
+            (1, 2, 0),
+            (2, 0, 1),
+            (2, 1, 0),
+        ] {
+            let mut shuffled = vec![entries[x].clone(), entries[y].clone(), entries[z].clone()];
+            sort_entries(&mut shuffled);
+            assert_eq!(ids(&shuffled), ["a.conf", "z.conf", "m.conf"]);
+        }
+    }
+
+    #[test]
+    fn entries_from_several_partitions() {
+        struct Found(u32, BlsEntry);
+        impl AsRef<BlsEntry> for Found {
+            fn as_ref(&self) -> &BlsEntry {
+                &self.1
+            }
+        }
+        // a bad entry on the first partition, a good one on the second
+        let mut entries = vec![
+            Found(1, entry("new+0-3.conf", "linux /k\nversion 6.10")),
+            Found(2, entry("old.conf", "linux /k\nversion 6.1")),
+        ];
+        sort_entries(&mut entries);
+        let bootable: Vec<_> = bootable_entries(&entries).map(|f| f.0).collect();
+        assert_eq!(bootable, [2]);
+    }
+
+    #[test]
+    fn bad_entries_are_the_last_resort() {
+        let entries = vec![
+            entry("a+0-3.conf", "linux /k"),
+            entry("b+0-1.conf", "linux /k"),
+        ];
+        assert_eq!(bootable_entries(&entries).count(), 2);
+    }
+}
@@ -0,0 +1,787 @@
+extern crate alloc;
+
+use core::{fmt::Display, mem::size_of};
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use regex::*;
+use uefi::{
+    cstr16,
+    proto::device_path::{media::PartitionSignature, DevicePath, DevicePathNodeEnum},
+    table::runtime::{VariableAttributes, VariableVendor},
+    CStr16, CString16, Char16,
+};
+
+use crate::var_store::VariableStore;
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum LoadOptionAttributesBits {
+    LoadOptionActive = 0x00000001,
+    LoadOptionForceReconnect = 0x00000002,
+    LoadOptionHidden = 0x00000008,
+    LoadOptionCategory = 0x00001F00,
+    LoadOptionCategoryApp = 0x000000100,
+    LoadOptionCategoryBoot = 0x000000000,
+}
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptionAttributes(u32);
+
+impl LoadOptionAttributes {
+    pub fn from(data: u32) -> Self {
+        LoadOptionAttributes(data)
+    }
+    fn is_active(&self) -> bool {
+        self.0 & LoadOptionAttributesBits::LoadOptionActive as u32 != 0
+    }
+    fn is_force_reconnect(&self) -> bool {
+        self.0 & LoadOptionAttributesBits::LoadOptionForceReconnect as u32 != 0
+    }
+    fn is_hidden(&self) -> bool {
+        self.0 & LoadOptionAttributesBits::LoadOptionHidden as u32 != 0
+    }
+    fn category(&self) -> u32 {
+        self.0 & LoadOptionAttributesBits::LoadOptionCategory as u32
+    }
+    fn is_category_app(&self) -> bool {
+        self.0 & LoadOptionAttributesBits::LoadOptionCategoryApp as u32 != 0
+    }
+    fn is_category_boot(&self) -> bool {
+        self.0 & LoadOptionAttributesBits::LoadOptionCategoryBoot as u32 != 0
+    }
+}
+
+impl From<LoadOptionAttributes> for u32 {
+    fn from(data: LoadOptionAttributes) -> Self {
+        data.0
+    }
+}
+
+impl Display for LoadOptionAttributes {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "LoadOptionAttributes {{ active: {}, force_reconnect: {}, hidden: {}, category: {}, category_app: {}, category_boot: {} }}",
+            self.is_active(),
+            self.is_force_reconnect(),
+            self.is_hidden(),
+            self.category(),
+            self.is_category_app(),
+            self.is_category_boot()
+        )
+    }
+}
+
+// Load option families, UEFI spec 3.1. Each is a set of `<Prefix>####`
+// variables processed in the order given by `<Prefix>Order`, except
+// PlatformRecovery#### which has no order variable and is processed in
+// numerical order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadOptionType {
+    Boot,
+    Driver,
+    SysPrep,
+    PlatformRecovery,
+}
+
+impl LoadOptionType {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            LoadOptionType::Boot => "Boot",
+            LoadOptionType::Driver => "Driver",
+            LoadOptionType::SysPrep => "SysPrep",
+            LoadOptionType::PlatformRecovery => "PlatformRecovery",
+        }
+    }
+
+    fn order_var_name(self) -> Option<&'static CStr16> {
+        match self {
+            LoadOptionType::Boot => Some(cstr16!("BootOrder")),
+            LoadOptionType::Driver => Some(cstr16!("DriverOrder")),
+            LoadOptionType::SysPrep => Some(cstr16!("SysPrepOrder")),
+            LoadOptionType::PlatformRecovery => None,
+        }
+    }
+
+    pub fn var_name(self, index: usize) -> Result<CString16> {
+        CString16::try_from(format!("{}{:04X}", self.prefix(), index).as_str())
+            .map_err(anyhow::Error::msg)
+    }
+}
+
+// Attributes of load option and order variables
+pub const LOAD_OPTION_VAR_ATTRIBUTES: VariableAttributes = VariableAttributes::NON_VOLATILE
+    .union(VariableAttributes::BOOTSERVICE_ACCESS)
+    .union(VariableAttributes::RUNTIME_ACCESS);
+
+pub struct EfiLoadOption {
+    pub attributes: LoadOptionAttributes,
+    pub description: CString16,
+    pub device_path_list: Vec<Box<DevicePath>>,
+    pub optional_data: Option<Vec<u8>>,
+}
+
+trait TryFromNeBytes: Sized {
+    fn try_from_ne_bytes(data: &[u8]) -> Result<Self>;
+}
+
+impl TryFromNeBytes for CString16 {
+    fn try_from_ne_bytes(data: &[u8]) -> Result<Self> {
+        let data_len = data.len() & !1;
+        let mut ret = CString16::new();
+
+        let mut index = 0;
+        while index < data_len {
+            let c = u16::from_ne_bytes([data[index], data[index + 1]]);
+            index += 2;
+            if c == 0 {
+                return Ok(ret);
+            }
+            // pushing of NULL character is invalid
+            ret.push(Char16::try_from(c).map_err(anyhow::Error::msg)?);
+        }
+        Err(anyhow!("CString16 not null-terminated"))
+    }
+}
+
+// Device path as text without the firmware's DevicePathToText, which isn't
+// there at runtime. Only the nodes boot entries are made of are decoded
+pub fn device_path_text(device_path: &DevicePath) -> String {
+    let mut nodes = Vec::new();
+    for node in device_path.node_iter() {
+        let text = match node.as_enum() {
+            Ok(DevicePathNodeEnum::MediaHardDrive(hd)) => match hd.partition_signature() {
+                PartitionSignature::Guid(guid) => {
+                    format!("HD({},GPT,{})", hd.partition_number(), guid)
+                }
+                PartitionSignature::Mbr(sig) => format!(
+                    "HD({},MBR,0x{:08x})",
+                    hd.partition_number(),
+                    u32::from_le_bytes(sig)
+                ),
+                _ => format!("HD({})", hd.partition_number()),
+            },
+            Ok(DevicePathNodeEnum::MessagingNvmeNamespace(n)) => {
+                format!("NVMe(0x{:x})", n.namespace_identifier())
+            }
+            Ok(DevicePathNodeEnum::MediaFilePath(f)) => f
+                .path_name()
+                .to_cstring16()
+                .map(|p| p.to_string())
+                .unwrap_or_else(|_| "File(?)".to_string()),
+            _ => format!("Path({},{})", node.device_type().0, node.sub_type().0),
+        };
+        nodes.push(text);
+    }
+    nodes.join("/")
+}
+
+// Copy a device path out of variable data, checking that every node fits in
+// `data` and that the path is terminated
+pub fn device_path_from_bytes(data: &[u8]) -> Result<Box<DevicePath>> {
+    let mut offset = 0;
+    loop {
+        let header = data
+            .get(offset..offset + 4)
+            .context("device path is not terminated")?;
+        let length = u16::from_ne_bytes([header[2], header[3]]) as usize;
+        if length < 4 || offset + length > data.len() {
+            return Err(anyhow!("invalid device path node length {}", length));
+        }
+        offset += length;
+        // End of Entire Device Path
+        if header[0] == 0x7F && header[1] == 0xFF {
+            break;
+        }
+    }
+    Ok(unsafe { DevicePath::from_ffi_ptr(data.as_ptr() as *const _) }.to_boxed())
+}
+
+impl TryFrom<&[u8]> for EfiLoadOption {
+    type Error = anyhow::Error;
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < 6 {
+            return Err(anyhow!("EfiBootOption data too short"));
+        }
+
+        let (attribute_bytes, data) = data.split_at(size_of::<u32>());
+        let attributes =
+            u32::from_ne_bytes(attribute_bytes.try_into().map_err(anyhow::Error::msg)?);
+
+        let (file_path_list_length_bytes, data) = data.split_at(size_of::<u16>());
+        let file_path_list_length = u16::from_ne_bytes(
+            file_path_list_length_bytes
+                .try_into()
+                .map_err(anyhow::Error::msg)?,
+        ) as usize;
+        if file_path_list_length == 0 {
+            return Err(anyhow!("EfiBootOption file_path_list_length is zero"));
+        }
+
+        let description = CString16::try_from_ne_bytes(data)?;
+        let (_, data) = data.split_at(description.num_bytes());
+
+        // FilePathList is one or more device paths, each parsed within the
+        // bytes the header says the list takes
+        let mut file_path_list = data
+            .get(..file_path_list_length)
+            .context("EfiBootOption file_path_list_length too long")?;
+        let mut device_path_list: Vec<_> = Vec::new();
+        while !file_path_list.is_empty() {
+            let device_path = device_path_from_bytes(file_path_list)?;
+            (_, file_path_list) = file_path_list.split_at(device_path.as_bytes().len());
+            device_path_list.push(device_path);
+        }
+
+        let data = &data[file_path_list_length..];
+        let optional_data = if !data.is_empty() {
+            Some(data.to_vec())
+        } else {
+            None
+        };
+
+        Ok(EfiLoadOption {
+            attributes: LoadOptionAttributes::from(attributes),
+            description,
+            device_path_list,
+            optional_data,
+        })
+    }
+}
+
+impl Display for EfiLoadOption {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{{ attributes: {}, description: {}, device_path: {:?}, optional_data: {:?} }}",
+            self.attributes,
+            self.description,
+            self.device_path_list
+                .iter()
+                .map(|p| device_path_text(p))
+                .collect::<Vec<_>>(),
+            self.optional_data
+        )
+    }
+}
+
+impl From<EfiLoadOption> for Vec<u8> {
+    fn from(data: EfiLoadOption) -> Vec<u8> {
+        Vec::from(&data)
+    }
+}
+
+impl From<&EfiLoadOption> for Vec<u8> {
+    fn from(data: &EfiLoadOption) -> Vec<u8> {
+        let mut v = Vec::new();
+        let file_path_list_length: u16 =
+            data.device_path_list
+                .iter()
+                .fold(0, |acc, p| acc + p.as_bytes().len()) as u16;
+
+        v.extend_from_slice(&data.attributes.0.to_ne_bytes());
+        v.extend_from_slice(&file_path_list_length.to_ne_bytes());
+        v.extend_from_slice(data.description.as_bytes());
+
+        for device_path in data.device_path_list.iter() {
+            v.extend_from_slice(device_path.as_bytes());
+        }
+
+        if let Some(optional_data) = &data.optional_data {
+            v.extend_from_slice(optional_data);
+        }
+        v
+    }
+}
+impl EfiLoadOption {
+    pub fn new_from_variable(
+        store: &dyn VariableStore,
+        option_type: LoadOptionType,
+        index: usize,
+    ) -> Result<Self> {
+        let name = option_type.var_name(index)?;
+        let (value, _) = store
+            .get(&name, &VariableVendor::GLOBAL_VARIABLE)?
+            .with_context(|| format!("{} not found", name))?;
+        EfiLoadOption::try_from(value.as_slice())
+    }
+
+    pub fn store(
+        &self,
+        store: &dyn VariableStore,
+        option_type: LoadOptionType,
+        index: usize,
+    ) -> Result<()> {
+        store.set(
+            &option_type.var_name(index)?,
+            &VariableVendor::GLOBAL_VARIABLE,
+            LOAD_OPTION_VAR_ATTRIBUTES,
+            &Vec::from(self),
+        )
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.attributes.is_active()
+    }
+
+    // Optional data as a command line, if it is a null-terminated UCS-2 string
+    pub fn optional_data_as_cstring16(&self) -> Option<CString16> {
+        CString16::try_from_ne_bytes(self.optional_data.as_deref()?).ok()
+    }
+}
+
+#[derive(Debug)]
+pub struct EfiBootOrder {
+    pub boot_order: Vec<u16>,
+}
+
+impl TryFrom<&[u8]> for EfiBootOrder {
+    type Error = anyhow::Error;
+    fn try_from(data: &[u8]) -> Result<Self> {
+        let entries = data.chunks_exact(2);
+        if !entries.remainder().is_empty() {
+            return Err(anyhow!("BootOrder has odd length {}", data.len()));
+        }
+        let boot_order = entries.map(|c| u16::from_ne_bytes([c[0], c[1]])).collect();
+        Ok(EfiBootOrder { boot_order })
+    }
+}
+
+impl EfiBootOrder {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut v = Vec::new();
+        for order in &self.boot_order {
+            v.extend_from_slice(&order.to_ne_bytes());
+        }
+        v
+    }
+
+    // A missing order variable is an empty order
+    pub fn new_from_variable(
+        store: &dyn VariableStore,
+        option_type: LoadOptionType,
+    ) -> Result<Self> {
+        let value = match option_type.order_var_name() {
+            Some(name) => store.get(name, &VariableVendor::GLOBAL_VARIABLE)?,
+            None => None,
+        };
+        match value {
+            Some((value, _)) => EfiBootOrder::try_from(value.as_slice()),
+            None => Ok(EfiBootOrder {
+                boot_order: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn store(&self, store: &dyn VariableStore, option_type: LoadOptionType) -> Result<()> {
+        let name = option_type
+            .order_var_name()
+            .with_context(|| format!("{} options have no order variable", option_type.prefix()))?;
+        store.set(
+            name,
+            &VariableVendor::GLOBAL_VARIABLE,
+            LOAD_OPTION_VAR_ATTRIBUTES,
+            &self.as_bytes(),
+        )
+    }
+}
+
+// All load options of one family together with their order variable
+pub struct EfiBootManager {
+    pub option_type: LoadOptionType,
+    pub boot_options: Vec<(usize, EfiLoadOption)>,
+    pub boot_order: EfiBootOrder,
+}
+
+impl EfiBootManager {
+    pub fn new_from_variables(
+        store: &dyn VariableStore,
+        option_type: LoadOptionType,
+    ) -> Result<Self> {
+        let boot_order = EfiBootOrder::new_from_variable(store, option_type)?;
+        let mut boot_options = Vec::new();
+
+        // try reading all options of this family from variables
+        let re = Regex::new(&format!(r"^{}([0-9A-Fa-f]{{4}})$", option_type.prefix())).unwrap();
+        let var_key = store.keys()?;
+
+        for (var, vendor) in var_key.iter() {
+            if *vendor != VariableVendor::GLOBAL_VARIABLE {
+                continue;
+            }
+            if let Some(cap) = re.captures(&var.to_string()) {
+                // gone since the keys were read
+                let Some((value, _)) = store.get(var, &VariableVendor::GLOBAL_VARIABLE)? else {
+                    continue;
+                };
+                let index = usize::from_str_radix(&cap[1], 16).map_err(anyhow::Error::msg)?;
+                match EfiLoadOption::try_from(value.as_slice()) {
+                    Ok(boot_option) => boot_options.push((index, boot_option)),
+                    Err(e) => warn!("Skipping invalid {}: {:?}", var, e),
+                }
+            }
+        }
+        boot_options.sort_by_key(|(i, _)| *i);
+
+        Ok(EfiBootManager {
+            option_type,
+            boot_options,
+            boot_order,
+        })
+    }
+
+    pub fn option(&self, index: usize) -> Option<&EfiLoadOption> {
+        self.boot_options
+            .iter()
+            .find(|(i, _)| *i == index)
+            .map(|(_, option)| option)
+    }
+
+    // Options in processing order. Order entries without a variable are skipped
+    pub fn ordered_options(&self) -> Vec<(usize, &EfiLoadOption)> {
+        if self.option_type == LoadOptionType::PlatformRecovery {
+            return self.boot_options.iter().map(|(i, o)| (*i, o)).collect();
+        }
+        self.boot_order
+            .boot_order
+            .iter()
+            .filter_map(|i| Some((*i as usize, self.option(*i as usize)?)))
+            .collect()
+    }
+
+    // Create or replace an option variable
+    pub fn set_option(
+        &mut self,
+        store: &dyn VariableStore,
+        index: usize,
+        option: EfiLoadOption,
+    ) -> Result<()> {
+        option.store(store, self.option_type, index)?;
+        self.boot_options.retain(|(i, _)| *i != index);
+        self.boot_options.push((index, option));
+        self.boot_options.sort_by_key(|(i, _)| *i);
+        Ok(())
+    }
+
+    // Delete an option variable and drop it from the order
+    pub fn delete_option(&mut self, store: &dyn VariableStore, index: usize) -> Result<()> {
+        store.delete(
+            &self.option_type.var_name(index)?,
+            &VariableVendor::GLOBAL_VARIABLE,
+        )?;
+        self.boot_options.retain(|(i, _)| *i != index);
+        if self.boot_order.boot_order.contains(&(index as u16)) {
+            self.boot_order.boot_order.retain(|i| *i as usize != index);
+            self.boot_order.store(store, self.option_type)?;
+        }
+        Ok(())
+    }
+
+    // Put an option into the order, first or last, unless it is already there
+    pub fn add_to_order(
+        &mut self,
+        store: &dyn VariableStore,
+        index: usize,
+        first: bool,
+    ) -> Result<()> {
+        if self.boot_order.boot_order.contains(&(index as u16)) {
+            return Ok(());
+        }
+        if first {
+            self.boot_order.boot_order.insert(0, index as u16);
+        } else {
+            self.boot_order.boot_order.push(index as u16);
+        }
+        self.boot_order.store(store, self.option_type)
+    }
+
+    pub fn get_next_available_boot_index(&self) -> Result<usize> {
+        // if there are no boot options, return 0
+        if self.boot_options.is_empty() {
+            return Ok(0);
+        }
+        // if the last index equals the last boot option index, return the next index
+        if self.boot_options.last().unwrap().0 == self.boot_options.len() - 1 {
+            return Ok(self.boot_options.len());
+        }
+
+        // if all above conditions are false, find the first available index
+        let index = self
+            .boot_options
+            .iter()
+            .enumerate()
+            .find(|(i, (index, _))| *i != *index)
+            .context("no available boot index found")?
+            .0;
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jumpstart_vars::JUMPSTART_VENDOR, var_store::MemoryStore};
+    use uefi::{
+        guid,
+        proto::device_path::{
+            build::{self, DevicePathBuilder},
+            media::{PartitionFormat, PartitionSignature},
+        },
+        Guid,
+    };
+
+    const ESP_GUID: Guid = guid!("2f3c6a0e-5b1d-4c8e-9a47-0d6e1f2b3c4d");
+
+    fn device_path(partition: u32, file: &str) -> Box<DevicePath> {
+        let file = CString16::try_from(file).unwrap();
+        let mut buffer = Vec::new();
+        DevicePathBuilder::with_vec(&mut buffer)
+            .push(&build::media::HardDrive {
+                partition_number: partition,
+                partition_start: 2048,
+                partition_size: 1_048_576,
+                partition_signature: PartitionSignature::Guid(ESP_GUID),
+                partition_format: PartitionFormat::GPT,
+            })
+            .unwrap()
+            .push(&build::media::FilePath { path_name: &file })
+            .unwrap()
+            .finalize()
+            .unwrap()
+            .to_boxed()
+    }
+
+    fn option(description: &str, optional_data: Option<&[u8]>) -> EfiLoadOption {
+        EfiLoadOption {
+            attributes: LoadOptionAttributes::from(
+                LoadOptionAttributesBits::LoadOptionActive as u32,
+            ),
+            description: CString16::try_from(description).unwrap(),
+            device_path_list: alloc::vec![device_path(1, "\\EFI\\BOOT\\BOOTX64.EFI")],
+            optional_data: optional_data.map(|d| d.to_vec()),
+        }
+    }
+
+    fn set(store: &MemoryStore, name: &str, vendor: VariableVendor, data: &[u8]) {
+        store
+            .set(
+                &CString16::try_from(name).unwrap(),
+                &vendor,
+                LOAD_OPTION_VAR_ATTRIBUTES,
+                data,
+            )
+            .unwrap();
+    }
+
+    fn set_order(store: &MemoryStore, name: &str, order: &[u16]) {
+        let order = EfiBootOrder {
+            boot_order: order.to_vec(),
+        };
+        set(
+            store,
+            name,
+            VariableVendor::GLOBAL_VARIABLE,
+            &order.as_bytes(),
+        );
+    }
+
+    // The usual shape of a Dell server's NVRAM: several Boot#### entries,
+    // an order naming one that no longer exists, and other variables that
+    // look alike
+    fn dell_like_store() -> MemoryStore {
+        let store = MemoryStore::new();
+        for (name, description) in [
+            ("Boot0000", "Integrated NIC 1 Port 1 Partition 1"),
+            ("Boot0002", "ubuntu"),
+            ("Boot0001", "UEFI: PERC H755 Front"),
+            ("Driver0001", "NvmExpressDxe"),
+        ] {
+            set(
+                &store,
+                name,
+                VariableVendor::GLOBAL_VARIABLE,
+                &Vec::from(&option(description, None)),
+            );
+        }
+        set(
+            &store,
+            "Boot0003",
+            JUMPSTART_VENDOR,
+            &Vec::from(&option("other vendor", None)),
+        );
+        set_order(&store, "BootOrder", &[2, 0, 5]);
+        set(&store, "BootNext", VariableVendor::GLOBAL_VARIABLE, &[2, 0]);
+        store
+    }
+
+    fn indices(options: &[(usize, &EfiLoadOption)]) -> Vec<usize> {
+        options.iter().map(|(i, _)| *i).collect()
+    }
+
+    #[test]
+    fn load_option_round_trip() {
+        let original = option("jumpstart", Some(b"JSLO\x01menu"));
+        let data = Vec::from(&original);
+        let parsed = EfiLoadOption::try_from(data.as_slice()).unwrap();
+        assert_eq!(u32::from(parsed.attributes), u32::from(original.attributes));
+        assert_eq!(parsed.description, original.description);
+        assert_eq!(parsed.device_path_list.len(), 1);
+        assert_eq!(
+            parsed.device_path_list[0].as_bytes(),
+            original.device_path_list[0].as_bytes()
+        );
+        assert_eq!(parsed.optional_data, original.optional_data);
+        assert!(parsed.is_active());
+    }
+
+    #[test]
+    fn reads_one_family_in_order() {
+        let store = dell_like_store();
+        let manager = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+        let all: Vec<usize> = manager.boot_options.iter().map(|(i, _)| *i).collect();
+        assert_eq!(all, [0, 1, 2]);
+        assert_eq!(manager.boot_order.boot_order, [2, 0, 5]);
+        // Boot0005 is in the order but has no variable
+        assert_eq!(indices(&manager.ordered_options()), [2, 0]);
+        assert_eq!(manager.option(2).unwrap().description.to_string(), "ubuntu");
+
+        let drivers = EfiBootManager::new_from_variables(&store, LoadOptionType::Driver).unwrap();
+        assert_eq!(drivers.boot_options.len(), 1);
+        assert!(drivers.boot_order.boot_order.is_empty());
+    }
+
+    // Boot#### data damaged in the ways firmware and other tools leave it
+    fn invalid_options() -> Vec<Vec<u8>> {
+        let valid = option("ubuntu", None);
+        let data = Vec::from(&valid);
+        let path_offset = 6 + valid.description.num_bytes();
+        let path_len = valid.device_path_list[0].as_bytes().len();
+
+        let mut truncated = data.clone();
+        truncated.truncate(path_offset + path_len - 2);
+        // the list length stops before the End node
+        let mut unterminated = data.clone();
+        unterminated[4..6].copy_from_slice(&(path_len as u16 - 4).to_ne_bytes());
+        let mut oversized_node = data.clone();
+        oversized_node[path_offset + 2..path_offset + 4].copy_from_slice(&0x4000u16.to_ne_bytes());
+        let mut empty_list = data.clone();
+        empty_list[4..6].copy_from_slice(&0u16.to_ne_bytes());
+
+        alloc::vec![
+            alloc::vec![1, 2, 3],
+            truncated,
+            unterminated,
+            oversized_node,
+            empty_list,
+        ]
+    }
+
+    #[test]
+    fn invalid_option_is_skipped() {
+        for data in invalid_options() {
+            assert!(EfiLoadOption::try_from(data.as_slice()).is_err());
+            let store = dell_like_store();
+            set(&store, "Boot0004", VariableVendor::GLOBAL_VARIABLE, &data);
+            let manager = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+            assert!(manager.option(4).is_none());
+            assert_eq!(manager.boot_options.len(), 3);
+        }
+    }
+
+    #[test]
+    fn odd_length_order_is_an_error() {
+        let store = dell_like_store();
+        set(
+            &store,
+            "BootOrder",
+            VariableVendor::GLOBAL_VARIABLE,
+            &[2, 0, 0],
+        );
+        assert!(EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).is_err());
+    }
+
+    #[test]
+    fn platform_recovery_in_numeric_order() {
+        let store = MemoryStore::new();
+        for name in ["PlatformRecovery0002", "PlatformRecovery0000"] {
+            set(
+                &store,
+                name,
+                VariableVendor::GLOBAL_VARIABLE,
+                &Vec::from(&option("recovery", None)),
+            );
+        }
+        let manager =
+            EfiBootManager::new_from_variables(&store, LoadOptionType::PlatformRecovery).unwrap();
+        assert_eq!(indices(&manager.ordered_options()), [0, 2]);
+        assert!(manager
+            .boot_order
+            .store(&store, manager.option_type)
+            .is_err());
+    }
+
+    #[test]
+    fn set_option_and_order_are_stored() {
+        let store = dell_like_store();
+        let mut manager = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+        let index = manager.get_next_available_boot_index().unwrap();
+        assert_eq!(index, 3);
+        manager
+            .set_option(&store, index, option("jumpstart", None))
+            .unwrap();
+        manager.add_to_order(&store, index, true).unwrap();
+        // already there, nothing changes
+        manager.add_to_order(&store, index, false).unwrap();
+
+        let reloaded = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+        assert_eq!(reloaded.boot_order.boot_order, [3, 2, 0, 5]);
+        assert_eq!(
+            reloaded.option(3).unwrap().description.to_string(),
+            "jumpstart"
+        );
+    }
+
+    #[test]
+    fn delete_option_drops_it_from_the_order() {
+        let store = dell_like_store();
+        let mut manager = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+        manager.delete_option(&store, 2).unwrap();
+        assert!(manager.delete_option(&store, 2).is_err());
+
+        let reloaded = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+        assert!(reloaded.option(2).is_none());
+        assert_eq!(reloaded.boot_order.boot_order, [0, 5]);
+    }
+
+    #[test]
+    fn next_available_index_fills_gaps() {
+        let store = MemoryStore::new();
+        let manager = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+        assert_eq!(manager.get_next_available_boot_index().unwrap(), 0);
+
+        for name in ["Boot0000", "Boot0001", "Boot0003"] {
+            set(
+                &store,
+                name,
+                VariableVendor::GLOBAL_VARIABLE,
+                &Vec::from(&option("entry", None)),
+            );
+        }
+        let manager = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+        assert_eq!(manager.get_next_available_boot_index().unwrap(), 2);
+    }
+
+    #[test]
+    fn device_path_as_text() {
+        assert_eq!(
+            device_path_text(&device_path(1, "\\EFI\\BOOT\\BOOTX64.EFI")),
+            "HD(1,GPT,2f3c6a0e-5b1d-4c8e-9a47-0d6e1f2b3c4d)/\\EFI\\BOOT\\BOOTX64.EFI"
+        );
+    }
+}
@@ -0,0 +1,731 @@
+extern crate alloc;
+
+use core::{fmt::Display, str::FromStr};
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn, LevelFilter};
+use uefi::{proto::device_path::media::PartitionSignature, CString16, Guid};
+
+use crate::{
+    boot_vars::EfiLoadOption,
+    device_path::{DevicePathExt, DEFAULT_LOADER},
+    gpt::{EFI_SYSTEM_PARTITION_GUID, XBOOTLDR_PARTITION_GUID},
+    smbios::SystemInfo,
+};
+
+const DEFAULT_SLOT_TRIES: u8 = 3;
+const DEFAULT_HOTKEY_TIMEOUT_MS: u64 = 1000;
+
+// Default NVMe driver, relative to the jumpstart directory
+pub const NVME_DRIVER_FILE: &str = r"drivers\NvmExpressDxe.efi";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionSelector {
+    Label(String),
+    Uuid(Guid),
+}
+
+// GPT identity of the partition behind a filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionId {
+    pub type_guid: Guid,
+    pub uuid: Guid,
+    pub label: String,
+}
+
+impl PartitionId {
+    pub fn matches(&self, selector: &PartitionSelector) -> bool {
+        match selector {
+            PartitionSelector::Label(label) => self.label == *label,
+            PartitionSelector::Uuid(uuid) => self.uuid == *uuid,
+        }
+    }
+
+    pub fn is_boot_partition(&self) -> bool {
+        self.type_guid == EFI_SYSTEM_PARTITION_GUID || self.type_guid == XBOOTLDR_PARTITION_GUID
+    }
+}
+
+// A partition and a file on it, e.g. `partlabel=ESP-A:\EFI\foo\grubx64.efi`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootTarget {
+    pub partition: PartitionSelector,
+    pub path: String,
+}
+
+impl BootTarget {
+    pub fn path_cstr16(&self) -> Result<CString16> {
+        CString16::try_from(self.path.as_str()).map_err(anyhow::Error::msg)
+    }
+
+    // The partition the target selects, the first one if several match
+    pub fn select<'a, P>(
+        &self,
+        partitions: &'a [P],
+        id: impl Fn(&P) -> Option<&PartitionId>,
+    ) -> Result<&'a P> {
+        let matches: Vec<_> = partitions
+            .iter()
+            .filter(|p| id(p).is_some_and(|id| id.matches(&self.partition)))
+            .collect();
+        if matches.len() > 1 {
+            warn!(
+                "{} partitions match {}, using the first one",
+                matches.len(),
+                self
+            );
+        }
+        matches
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("no NVMe partition matches {}", self))
+    }
+
+    // Partition GUID and file of a Boot#### entry, the default loader if the
+    // entry stops at the partition
+    pub fn from_load_option(option: &EfiLoadOption) -> Option<Self> {
+        let device_path = option
+            .device_path_list
+            .iter()
+            .find(|p| p.hard_drive().is_some())?;
+        let PartitionSignature::Guid(uuid) = device_path.hard_drive()?.partition_signature() else {
+            return None;
+        };
+        let path = match device_path.file_path() {
+            Some(file_path) => file_path.path_name().to_cstring16().ok()?.to_string(),
+            None => DEFAULT_LOADER.to_string(),
+        };
+        Some(BootTarget {
+            partition: PartitionSelector::Uuid(uuid),
+            path,
+        })
+    }
+}
+
+impl FromStr for BootTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // the path always starts with a backslash, so `:\` separates it from
+        // the selector even if the label itself contains a colon
+        let split = s
+            .find(":\\")
+            .with_context(|| format!("missing ':\\path' in boot target '{}'", s))?;
+        let (selector, path) = (&s[..split], &s[split + 1..]);
+
+        let partition = if let Some(label) = selector.strip_prefix("partlabel=") {
+            PartitionSelector::Label(label.to_string())
+        } else if let Some(uuid) = selector.strip_prefix("partuuid=") {
+            PartitionSelector::Uuid(
+                Guid::try_parse(uuid).map_err(|_| anyhow!("invalid partuuid '{}'", uuid))?,
+            )
+        } else {
+            return Err(anyhow!("unknown partition selector '{}'", selector));
+        };
+
+        let target = BootTarget {
+            partition,
+            path: path.to_string(),
+        };
+        // make sure the path is representable as UCS-2 before we need it
+        target.path_cstr16()?;
+        Ok(target)
+    }
+}
+
+impl Display for BootTarget {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.partition {
+            PartitionSelector::Label(label) => write!(f, "partlabel={}:{}", label, self.path),
+            PartitionSelector::Uuid(uuid) => write!(f, "partuuid={}:{}", uuid, self.path),
+        }
+    }
+}
+
+// What jumpstart does when started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Boot,
+    // register the NVMe driver as a firmware Driver#### entry
+    Install,
+    // remove the Driver#### entry again
+    Uninstall,
+    // create companion Boot#### entries for NVMe targets, see sync.rs
+    Sync,
+    // remove the companion entries again
+    Unsync,
+    // write a hardware/firmware inventory, see inventory.rs
+    Inventory,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "boot" => Ok(Mode::Boot),
+            "install" => Ok(Mode::Install),
+            "uninstall" => Ok(Mode::Uninstall),
+            "sync" => Ok(Mode::Sync),
+            "unsync" => Ok(Mode::Unsync),
+            "inventory" => Ok(Mode::Inventory),
+            _ => Err(anyhow!("unknown mode '{}'", s)),
+        }
+    }
+}
+
+// How controllers are connected once the NVMe driver is loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectStrategy {
+    // every handle to whatever drivers the firmware picks
+    #[default]
+    All,
+    // every handle to the NVMe driver only. Faster, but raises a CPU
+    // exception on some Dell firmware
+    Driver,
+}
+
+impl FromStr for ConnectStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all" => Ok(ConnectStrategy::All),
+            "driver" => Ok(ConnectStrategy::Driver),
+            _ => Err(anyhow!("unknown connect strategy '{}'", s)),
+        }
+    }
+}
+
+// Ways to leave jumpstart without booting anything. Written as `reboot`,
+// `warm-reboot`, `shutdown`, `firmware-setup` or `reboot-to:XXXX` with the
+// hex Boot#### index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Reboot,
+    WarmReboot,
+    Shutdown,
+    FirmwareSetup,
+    // one-shot boot of a Boot#### entry. The firmware may not see the entry's
+    // disk, so jumpstart boots it from a `target=` in JumpstartConfigOnce
+    RebootTo(u16),
+}
+
+impl FromStr for PowerAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reboot" => Ok(PowerAction::Reboot),
+            "warm-reboot" => Ok(PowerAction::WarmReboot),
+            "shutdown" => Ok(PowerAction::Shutdown),
+            "firmware-setup" => Ok(PowerAction::FirmwareSetup),
+            _ => {
+                let index = s
+                    .strip_prefix("reboot-to:")
+                    .ok_or_else(|| anyhow!("unknown power action '{}'", s))?;
+                u16::from_str_radix(index, 16)
+                    .map(PowerAction::RebootTo)
+                    .map_err(|_| anyhow!("invalid Boot#### index '{}'", index))
+            }
+        }
+    }
+}
+
+impl Display for PowerAction {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PowerAction::Reboot => write!(f, "reboot"),
+            PowerAction::WarmReboot => write!(f, "warm-reboot"),
+            PowerAction::Shutdown => write!(f, "shutdown"),
+            PowerAction::FirmwareSetup => write!(f, "firmware-setup"),
+            PowerAction::RebootTo(index) => write!(f, "reboot-to:{:04X}", index),
+        }
+    }
+}
+
+// Selector of a `[model ...]` config section: comma separated
+// `field=value` conditions that must all hold. A value ending in `*`
+// matches any value starting with the rest
+fn model_matches(selector: &str, system: Option<&SystemInfo>) -> Result<bool> {
+    // look fields up even without SMBIOS so typos are reported
+    let unknown = SystemInfo::default();
+    let system = system.unwrap_or(&unknown);
+    let mut matches = true;
+    for condition in selector.split(',') {
+        let (field, expected) = condition
+            .split_once('=')
+            .with_context(|| format!("expected field=value, got '{}'", condition))?;
+        let value = system.field(field.trim())?;
+        let expected = expected.trim();
+        matches &= value.is_some_and(|value| match expected.strip_suffix('*') {
+            Some(prefix) => value.starts_with(prefix),
+            None => value == expected,
+        });
+    }
+    Ok(matches)
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(anyhow!("expected a boolean, got '{}'", value)),
+    }
+}
+
+// Settings from the config file and jumpstart's load options. Both are
+// `key=value` pairs: one per line in the file (`#` starts a comment),
+// whitespace separated in load options, where `menu` and `dry-run` may also
+// be given as bare flags and `config=` picks another config file.
+// Layers, each overriding the ones before it: defaults, config file, SMBIOS
+// OEM strings, JumpstartConfig, JumpstartConfigOnce, load options.
+// `[model ...]` sections in the file override the lines before them on
+// matching systems, see model_matches().
+// Switches that only enable something (verify, menu, dry_run) stay on once
+// any layer sets them.
+#[derive(Debug, Default)]
+pub struct Config {
+    // tried in order before falling back to Boot#### entries
+    pub targets: Vec<BootTarget>,
+    // A/B slots, both must be set to enable slot selection
+    pub slot_a: Option<BootTarget>,
+    pub slot_b: Option<BootTarget>,
+    // tries given to a slot when the slot state is (re)initialized
+    pub slot_tries: Option<u8>,
+    // boot Boot Loader Spec entries from NVMe ESP/XBOOTLDR partitions
+    pub bls: Option<bool>,
+    // verify images against the trust list, see trust.rs
+    pub verify: bool,
+    pub mode: Option<Mode>,
+    // how long to listen for Key#### hotkeys at startup
+    pub hotkey_timeout_ms: Option<u64>,
+    // menu countdown in seconds, overrides the firmware's Timeout variable
+    pub timeout: Option<u16>,
+    // run right away instead of booting, mostly useful in load options
+    pub power_action: Option<PowerAction>,
+    // run when nothing could be booted
+    pub final_action: Option<PowerAction>,
+    // config file to read instead of the default, load options only
+    pub config_file: Option<String>,
+    pub log_level: Option<LevelFilter>,
+    // NVMe driver, relative to the jumpstart directory
+    pub driver: Option<String>,
+    // more drivers loaded after the NVMe driver
+    pub extra_drivers: Vec<String>,
+    pub connect: Option<ConnectStrategy>,
+    // keep companion Boot#### entries in sync on every boot
+    pub sync: bool,
+    // always show the boot menu without a countdown
+    pub menu: bool,
+    // go through the boot decisions without starting anything, see explain.rs
+    pub dry_run: bool,
+    // also write the dry run report to this file
+    pub report_file: Option<String>,
+    // where inventory mode writes its JSON
+    pub inventory_file: Option<String>,
+}
+
+impl Config {
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "target" => self.targets.push(value.parse()?),
+            "slot_a" => self.slot_a = Some(value.parse()?),
+            "slot_b" => self.slot_b = Some(value.parse()?),
+            "bls" => self.bls = Some(parse_bool(value)?),
+            "verify" => self.verify = parse_bool(value)?,
+            "mode" => self.mode = Some(value.parse()?),
+            "hotkey_timeout_ms" => {
+                self.hotkey_timeout_ms = Some(value.parse().map_err(anyhow::Error::msg)?)
+            }
+            "timeout" => self.timeout = Some(value.parse().map_err(anyhow::Error::msg)?),
+            "power_action" => self.power_action = Some(value.parse()?),
+            "final_action" => self.final_action = Some(value.parse()?),
+            "log" => self.log_level = Some(value.parse().map_err(anyhow::Error::msg)?),
+            "driver" => self.driver = Some(value.to_string()),
+            "extra_driver" => self.extra_drivers.push(value.to_string()),
+            "connect" => self.connect = Some(value.parse()?),
+            "sync" => self.sync = parse_bool(value)?,
+            "menu" => self.menu = parse_bool(value)?,
+            "dry_run" => self.dry_run = parse_bool(value)?,
+            "report" => self.report_file = Some(value.to_string()),
+            "inventory" => self.inventory_file = Some(value.to_string()),
+            "slot_tries" => self.slot_tries = Some(value.parse().map_err(anyhow::Error::msg)?),
+            _ => return Err(anyhow!("unknown config key '{}'", key)),
+        }
+        Ok(())
+    }
+
+    fn set_pair(&mut self, pair: &str) -> Result<()> {
+        let (key, value) = pair
+            .split_once('=')
+            .with_context(|| format!("expected key=value, got '{}'", pair))?;
+        self.set(key.trim(), value.trim())
+    }
+
+    pub fn parse_file(text: &str, system: Option<&SystemInfo>) -> Result<Self> {
+        let mut config = Config::default();
+        // settings of the current section, None outside of sections and in
+        // sections for other models
+        let mut section: Option<Config> = None;
+        let mut in_section = false;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let context = || format!("config line {}", n + 1);
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if let Some(section) = section.take() {
+                    config.merge(section);
+                }
+                let selector = header
+                    .strip_prefix("model ")
+                    .with_context(|| format!("unknown section '{}'", header))
+                    .with_context(context)?;
+                if model_matches(selector, system).with_context(context)? {
+                    info!("Applying config section [{}]", header);
+                    section = Some(Config::default());
+                }
+                in_section = true;
+                continue;
+            }
+            match (&mut section, in_section) {
+                (Some(section), _) => section.set_pair(line),
+                // other model: still check the line
+                (None, true) => Config::default().set_pair(line),
+                (None, false) => config.set_pair(line),
+            }
+            .with_context(context)?;
+        }
+        if let Some(section) = section {
+            config.merge(section);
+        }
+        Ok(config)
+    }
+
+    // Config lines from SMBIOS OEM strings, prefix already stripped
+    pub fn parse_oem_strings(strings: &[&str]) -> Result<Self> {
+        let mut config = Config::default();
+        for pair in strings {
+            config
+                .set_pair(pair)
+                .with_context(|| format!("OEM string 'jumpstart.{}'", pair))?;
+        }
+        Ok(config)
+    }
+
+    pub fn parse_load_options(text: &str) -> Result<Self> {
+        let mut config = Config::default();
+        for word in text.split_whitespace() {
+            match word.split_once('=') {
+                Some(("config", path)) => config.config_file = Some(path.to_string()),
+                // marks sync companions, see sync.rs
+                Some(("companion", _)) => {}
+                Some(_) => config.set_pair(word)?,
+                None => match word {
+                    "menu" => config.menu = true,
+                    "dry-run" => config.dry_run = true,
+                    // firmware shells pass the image name as the first word
+                    _ => info!("Ignoring load option '{}'", word),
+                },
+            }
+        }
+        Ok(config)
+    }
+
+    // Settings present in `other` replace ours
+    pub fn merge(&mut self, other: Config) {
+        if !other.targets.is_empty() {
+            self.targets = other.targets;
+        }
+        self.slot_a = other.slot_a.or(self.slot_a.take());
+        self.slot_b = other.slot_b.or(self.slot_b.take());
+        self.slot_tries = other.slot_tries.or(self.slot_tries);
+        self.bls = other.bls.or(self.bls);
+        // load options can turn verification on, never off
+        self.verify |= other.verify;
+        self.mode = other.mode.or(self.mode);
+        self.hotkey_timeout_ms = other.hotkey_timeout_ms.or(self.hotkey_timeout_ms);
+        self.timeout = other.timeout.or(self.timeout);
+        self.power_action = other.power_action.or(self.power_action);
+        self.final_action = other.final_action.or(self.final_action);
+        self.config_file = other.config_file.or(self.config_file.take());
+        self.log_level = other.log_level.or(self.log_level);
+        self.driver = other.driver.or(self.driver.take());
+        if !other.extra_drivers.is_empty() {
+            self.extra_drivers = other.extra_drivers;
+        }
+        self.connect = other.connect.or(self.connect);
+        self.sync |= other.sync;
+        self.menu |= other.menu;
+        self.dry_run |= other.dry_run;
+        self.report_file = other.report_file.or(self.report_file.take());
+        self.inventory_file = other.inventory_file.or(self.inventory_file.take());
+    }
+
+    pub fn slot_targets(&self) -> Option<(&BootTarget, &BootTarget)> {
+        Some((self.slot_a.as_ref()?, self.slot_b.as_ref()?))
+    }
+
+    pub fn bls(&self) -> bool {
+        self.bls.unwrap_or_default()
+    }
+
+    pub fn driver_file(&self) -> &str {
+        self.driver.as_deref().unwrap_or(NVME_DRIVER_FILE)
+    }
+
+    pub fn connect(&self) -> ConnectStrategy {
+        self.connect.unwrap_or_default()
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode.unwrap_or_default()
+    }
+
+    pub fn hotkey_timeout_ms(&self) -> u64 {
+        self.hotkey_timeout_ms.unwrap_or(DEFAULT_HOTKEY_TIMEOUT_MS)
+    }
+
+    pub fn slot_tries(&self) -> u8 {
+        self.slot_tries.unwrap_or(DEFAULT_SLOT_TRIES)
+    }
+}
+
+// Config from each layer. Later layers override earlier ones, see Config
+#[derive(Default)]
+pub struct ConfigLayers {
+    pub file: Option<Config>,
+    pub oem_strings: Option<Config>,
+    pub var: Option<Config>,
+    pub once: Option<Config>,
+    pub load_options: Option<Config>,
+}
+
+impl ConfigLayers {
+    pub fn merge(self) -> Config {
+        let mut config = self.file.unwrap_or_default();
+        for layer in [self.oem_strings, self.var, self.once, self.load_options]
+            .into_iter()
+            .flatten()
+        {
+            config.merge(layer);
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dell() -> SystemInfo {
+        SystemInfo {
+            manufacturer: Some("Dell Inc.".to_string()),
+            product: Some("PowerEdge R650".to_string()),
+            serial: Some("ABC1234".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn parse(text: &str) -> Config {
+        Config::parse_file(text, Some(&dell())).unwrap()
+    }
+
+    fn error(text: &str, system: Option<&SystemInfo>) -> String {
+        format!("{:#}", Config::parse_file(text, system).unwrap_err())
+    }
+
+    #[test]
+    fn sections_override_the_lines_before_them() {
+        let config = parse(
+            "timeout=1\n\
+             connect=all\n\
+             [model manufacturer=Dell*]\n\
+             timeout=2\n\
+             connect=driver\n\
+             [model product=PowerEdge R650, service_tag=ABC1234]\n\
+             timeout=3\n\
+             [model manufacturer=HP*]\n\
+             timeout=4\n\
+             log=debug\n",
+        );
+        // the later matching section wins, the HP one doesn't apply
+        assert_eq!(config.timeout, Some(3));
+        assert_eq!(config.connect(), ConnectStrategy::Driver);
+        assert_eq!(config.log_level, None);
+
+        // lines after a section header belong to the section
+        let config = parse("[model manufacturer=HP*]\ntimeout=4\n");
+        assert_eq!(config.timeout, None);
+
+        // without SMBIOS no section matches
+        let config = Config::parse_file("[model manufacturer=*]\ntimeout=4\n", None).unwrap();
+        assert_eq!(config.timeout, None);
+    }
+
+    #[test]
+    fn unknown_fields_are_errors() {
+        assert_eq!(
+            error("timeout=1\ntimout=2\n", None),
+            "config line 2: unknown config key 'timout'"
+        );
+        // also in sections for other models, and without SMBIOS
+        assert_eq!(
+            error("[model manufacturer=HP*]\nverbose=1\n", Some(&dell())),
+            "config line 2: unknown config key 'verbose'"
+        );
+        assert_eq!(
+            error("[model vendor=Dell*]\n", None),
+            "config line 1: unknown system field 'vendor'"
+        );
+        assert_eq!(
+            error("[vendor Dell]\n", None),
+            "config line 1: unknown section 'vendor Dell'"
+        );
+        assert_eq!(
+            error("# comment\ntimeout\n", None),
+            "config line 2: expected key=value, got 'timeout'"
+        );
+        assert!(Config::parse_file("bls=maybe", None).is_err());
+        assert!(Config::parse_file("target=partlabel=ESP", None).is_err());
+    }
+
+    #[test]
+    fn load_options() {
+        let config = Config::parse_load_options(
+            r"\EFI\jumpstart\jumpstart.efi menu dry-run config=test.cfg companion=1 target=partlabel=ESP-A:\EFI\a.efi",
+        )
+        .unwrap();
+        assert!(config.menu);
+        assert!(config.dry_run);
+        assert_eq!(config.config_file.as_deref(), Some("test.cfg"));
+        assert_eq!(config.targets.len(), 1);
+        assert!(Config::parse_load_options("mode=sleep").is_err());
+    }
+
+    #[test]
+    fn layers_override_in_order() {
+        let layer = |text: &str| Some(Config::parse_file(text, None).unwrap());
+        let layers = || ConfigLayers {
+            file: layer("timeout=1\nverify=1\ntarget=partlabel=A:\\a.efi\nlog=info"),
+            oem_strings: layer("timeout=2\nbls=1"),
+            var: layer("timeout=3\nverify=0"),
+            once: layer("timeout=4\ntarget=partlabel=B:\\b.efi"),
+            load_options: Some(Config::parse_load_options("timeout=5").unwrap()),
+        };
+        let config = layers().merge();
+        assert_eq!(config.timeout, Some(5));
+        // settings a layer doesn't have come from the ones below
+        assert_eq!(config.log_level, Some(LevelFilter::Info));
+        assert!(config.bls());
+        // verification stays on once any layer turns it on
+        assert!(config.verify);
+        // targets are replaced, not appended
+        assert_eq!(config.targets.len(), 1);
+        assert_eq!(config.targets[0].to_string(), r"partlabel=B:\b.efi");
+
+        let config = ConfigLayers {
+            load_options: None,
+            ..layers()
+        }
+        .merge();
+        assert_eq!(config.timeout, Some(4));
+        let config = ConfigLayers {
+            once: None,
+            load_options: None,
+            ..layers()
+        }
+        .merge();
+        assert_eq!(config.timeout, Some(3));
+        assert_eq!(config.targets[0].to_string(), r"partlabel=A:\a.efi");
+
+        let config = ConfigLayers::default().merge();
+        assert_eq!(config.timeout, None);
+        assert_eq!(config.slot_tries(), DEFAULT_SLOT_TRIES);
+        assert_eq!(config.driver_file(), NVME_DRIVER_FILE);
+    }
+
+    #[test]
+    fn boot_targets() {
+        let target: BootTarget = r"partlabel=ESP:A:\EFI\a.efi".parse().unwrap();
+        assert_eq!(
+            target.partition,
+            PartitionSelector::Label("ESP:A".to_string())
+        );
+        assert_eq!(target.path, r"\EFI\a.efi");
+        assert_eq!(target.to_string(), r"partlabel=ESP:A:\EFI\a.efi");
+
+        let uuid = "0c1d2e3f-4a5b-4c6d-8e7f-9a0b1c2d3e4f";
+        let target: BootTarget = format!(r"partuuid={}:\a.efi", uuid).parse().unwrap();
+        assert_eq!(target.to_string(), format!(r"partuuid={}:\a.efi", uuid));
+
+        assert!("partuuid=nope:\\a.efi".parse::<BootTarget>().is_err());
+        assert!("disk=1:\\a.efi".parse::<BootTarget>().is_err());
+    }
+
+    #[test]
+    fn targets_select_partitions() {
+        let id = |uuid: &str, label: &str| PartitionId {
+            type_guid: EFI_SYSTEM_PARTITION_GUID,
+            uuid: Guid::try_parse(uuid).unwrap(),
+            label: label.to_string(),
+        };
+        // filesystems on NVMe, one of them with an unknown identity
+        let partitions = [
+            ("nvme0", None),
+            (
+                "nvme1",
+                Some(id("0c1d2e3f-4a5b-4c6d-8e7f-9a0b1c2d3e4f", "ESP-A")),
+            ),
+            (
+                "nvme2",
+                Some(id("1c1d2e3f-4a5b-4c6d-8e7f-9a0b1c2d3e4f", "ESP-B")),
+            ),
+            (
+                "nvme3",
+                Some(id("2c1d2e3f-4a5b-4c6d-8e7f-9a0b1c2d3e4f", "ESP-B")),
+            ),
+        ];
+        let select = |target: &str| {
+            let target: BootTarget = target.parse().unwrap();
+            target
+                .select(&partitions, |(_, id)| id.as_ref())
+                .map(|(name, _)| *name)
+        };
+        assert_eq!(select(r"partlabel=ESP-A:\a.efi").unwrap(), "nvme1");
+        assert_eq!(
+            select(r"partuuid=2c1d2e3f-4a5b-4c6d-8e7f-9a0b1c2d3e4f:\a.efi").unwrap(),
+            "nvme3"
+        );
+        // cloned disks
+        assert_eq!(select(r"partlabel=ESP-B:\a.efi").unwrap(), "nvme2");
+        assert!(select(r"partlabel=ESP-C:\a.efi").is_err());
+    }
+
+    #[test]
+    fn power_actions() {
+        for text in [
+            "reboot",
+            "warm-reboot",
+            "shutdown",
+            "firmware-setup",
+            "reboot-to:000A",
+        ] {
+            assert_eq!(text.parse::<PowerAction>().unwrap().to_string(), text);
+        }
+        assert_eq!(
+            "reboot-to:a".parse::<PowerAction>().unwrap(),
+            PowerAction::RebootTo(10)
+        );
+        assert!("reboot-to:xyz".parse::<PowerAction>().is_err());
+        assert!("hibernate".parse::<PowerAction>().is_err());
+    }
+}
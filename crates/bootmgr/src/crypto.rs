@@ -0,0 +1,66 @@
+extern crate alloc;
+
+use alloc::{format, string::String};
+use ed25519_compact::{PublicKey, Signature};
+use sha2::{Digest, Sha256};
+
+// Hashes and signatures for image verification, from the sha2 and
+// ed25519-compact crates rather than our own code
+pub const SHA256_SIZE: usize = 32;
+pub const ED25519_PUBLIC_KEY_SIZE: usize = PublicKey::BYTES;
+pub const ED25519_SIGNATURE_SIZE: usize = Signature::BYTES;
+
+pub fn sha256(data: &[u8]) -> [u8; SHA256_SIZE] {
+    Sha256::digest(data).into()
+}
+
+// Check a detached Ed25519 signature (RFC 8032) over `message`
+pub fn ed25519_verify(
+    public_key: &[u8; ED25519_PUBLIC_KEY_SIZE],
+    message: &[u8],
+    signature: &[u8; ED25519_SIGNATURE_SIZE],
+) -> bool {
+    PublicKey::new(*public_key)
+        .verify(message, &Signature::new(*signature))
+        .is_ok()
+}
+
+// Lowercase hex, the way sha256sum prints digests
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex<const N: usize>(hex: &str) -> [u8; N] {
+        let bytes: alloc::vec::Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    #[test]
+    fn sha256_digest() {
+        assert_eq!(
+            to_hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn ed25519_signatures() {
+        // RFC 8032 7.1 TEST 3
+        let public_key =
+            from_hex("fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025");
+        let signature = from_hex(
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac\
+             18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        );
+        assert!(ed25519_verify(&public_key, b"\xaf\x82", &signature));
+        assert!(!ed25519_verify(&public_key, b"\xaf\x83", &signature));
+        assert!(!ed25519_verify(&[0; 32], b"\xaf\x82", &signature));
+    }
+}
@@ -0,0 +1,135 @@
+extern crate alloc;
+
+use alloc::{borrow::ToOwned, boxed::Box, vec::Vec};
+use anyhow::Result;
+use uefi::{
+    proto::device_path::{
+        build::{self, DevicePathBuilder},
+        media::{FilePath, HardDrive},
+        DevicePath, DevicePathNodeEnum, DeviceSubType, DeviceType,
+    },
+    CStr16, CString16,
+};
+
+// The removable media loader firmware boots from a partition when a path has
+// no file in it
+#[cfg(target_arch = "x86")]
+pub const DEFAULT_LOADER: &str = r"\EFI\BOOT\BOOTIA32.EFI";
+#[cfg(target_arch = "aarch64")]
+pub const DEFAULT_LOADER: &str = r"\EFI\BOOT\BOOTAA64.EFI";
+#[cfg(not(any(target_arch = "x86", target_arch = "aarch64")))]
+pub const DEFAULT_LOADER: &str = r"\EFI\BOOT\BOOTX64.EFI";
+
+// The nodes boot entries are matched by
+pub trait DevicePathExt {
+    fn file_path(&self) -> Option<&FilePath>;
+    fn hard_drive(&self) -> Option<&HardDrive>;
+    fn is_nvme(&self) -> bool;
+}
+
+impl DevicePathExt for DevicePath {
+    fn file_path(&self) -> Option<&FilePath> {
+        for inst in self.instance_iter() {
+            for node in inst.node_iter() {
+                if node.full_type() == (DeviceType::MEDIA, DeviceSubType::MEDIA_FILE_PATH) {
+                    let e_node = node.as_enum().unwrap();
+                    if let DevicePathNodeEnum::MediaFilePath(n) = e_node {
+                        return Some(n);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn hard_drive(&self) -> Option<&HardDrive> {
+        for inst in self.instance_iter() {
+            for node in inst.node_iter() {
+                if node.full_type() == (DeviceType::MEDIA, DeviceSubType::MEDIA_HARD_DRIVE) {
+                    let e_node = node.as_enum().unwrap();
+                    if let DevicePathNodeEnum::MediaHardDrive(n) = e_node {
+                        return Some(n);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn is_nvme(&self) -> bool {
+        for inst in self.instance_iter() {
+            for node in inst.node_iter() {
+                if node.full_type()
+                    == (
+                        DeviceType::MESSAGING,
+                        DeviceSubType::MESSAGING_NVME_NAMESPACE,
+                    )
+                {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+pub trait PartialEqExt {
+    fn eq(&self, other: &Self) -> bool;
+}
+
+impl PartialEqExt for HardDrive {
+    fn eq(&self, other: &Self) -> bool {
+        self.partition_number() == other.partition_number()
+            && self.partition_start() == other.partition_start()
+            && self.partition_size() == other.partition_size()
+            && self.partition_format() == other.partition_format()
+            && self.partition_number() == other.partition_number()
+            && self.partition_signature() == other.partition_signature()
+    }
+}
+
+// Build a new device path from `device_path` with `file_path` appended
+pub fn append_file_path(device_path: &DevicePath, file_path: &CStr16) -> Result<Box<DevicePath>> {
+    let mut backing_vector: Vec<u8> = Vec::new();
+    let mut new_device_path = DevicePathBuilder::with_vec(&mut backing_vector);
+    for node in device_path.node_iter() {
+        new_device_path = new_device_path.push(&node).map_err(anyhow::Error::msg)?;
+    }
+    new_device_path = new_device_path
+        .push(&build::media::FilePath {
+            path_name: file_path,
+        })
+        .map_err(anyhow::Error::msg)?;
+    Ok(new_device_path
+        .finalize()
+        .map_err(anyhow::Error::msg)?
+        .to_owned())
+}
+
+// `device_path` if it names a file, else the default loader on its partition
+pub fn with_default_loader(device_path: &DevicePath) -> Result<Box<DevicePath>> {
+    if device_path.file_path().is_some() {
+        return Ok(device_path.to_boxed());
+    }
+    let loader = CString16::try_from(DEFAULT_LOADER).map_err(anyhow::Error::msg)?;
+    append_file_path(device_path, &loader)
+}
+
+// Copy of `device_path` up to (not including) the first node of the given type
+pub fn truncate_device_path(
+    device_path: &DevicePath,
+    full_type: (DeviceType, DeviceSubType),
+) -> Result<Box<DevicePath>> {
+    let mut backing_vector: Vec<u8> = Vec::new();
+    let mut new_device_path = DevicePathBuilder::with_vec(&mut backing_vector);
+    for node in device_path.node_iter() {
+        if node.full_type() == full_type {
+            break;
+        }
+        new_device_path = new_device_path.push(&node).map_err(anyhow::Error::msg)?;
+    }
+    Ok(new_device_path
+        .finalize()
+        .map_err(anyhow::Error::msg)?
+        .to_owned())
+}
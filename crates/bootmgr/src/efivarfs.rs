@@ -0,0 +1,224 @@
+extern crate std;
+
+use std::{
+    format,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    vec::Vec,
+};
+
+use anyhow::{anyhow, Context, Result};
+use uefi::{
+    table::runtime::{VariableAttributes, VariableVendor},
+    CStr16, CString16, Guid,
+};
+
+use crate::var_store::VariableStore;
+
+// Where the kernel mounts efivarfs
+pub const EFIVARS_DIR: &str = "/sys/firmware/efi/efivars";
+
+// linux/fs.h. The flags are passed as an int, whatever the ioctl number says
+const FS_IOC_GETFLAGS: libc::c_ulong = 0x8008_6601;
+const FS_IOC_SETFLAGS: libc::c_ulong = 0x4008_6602;
+const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+
+// Length of `-<guid>` at the end of a file name
+const VENDOR_SUFFIX_LEN: usize = 37;
+
+// Variables are files named `<name>-<vendor guid>` holding the u32
+// attributes followed by the data. The kernel makes most of them immutable,
+// which has to be undone before writing or deleting one. Any directory laid
+// out the same way works, such as a copy taken from a real machine
+pub struct Efivarfs {
+    dir: PathBuf,
+}
+
+impl Efivarfs {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Efivarfs { dir: dir.into() }
+    }
+
+    fn path(&self, name: &CStr16, vendor: &VariableVendor) -> PathBuf {
+        self.dir.join(format!("{}-{}", name, vendor.0))
+    }
+}
+
+impl VariableStore for Efivarfs {
+    fn get(
+        &self,
+        name: &CStr16,
+        vendor: &VariableVendor,
+    ) -> Result<Option<(Vec<u8>, VariableAttributes)>> {
+        let path = self.path(name, vendor);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        if data.len() < 4 {
+            return Err(anyhow!("{} has no attributes", path.display()));
+        }
+        let attributes = u32::from_le_bytes(data[..4].try_into().unwrap());
+        Ok(Some((
+            data[4..].to_vec(),
+            VariableAttributes::from_bits_retain(attributes),
+        )))
+    }
+
+    fn set(
+        &self,
+        name: &CStr16,
+        vendor: &VariableVendor,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<()> {
+        let path = self.path(name, vendor);
+        if path.exists() {
+            clear_immutable(&path)?;
+        }
+        let mut buffer = attributes.bits().to_le_bytes().to_vec();
+        buffer.extend_from_slice(data);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        // efivarfs takes the variable in a single write
+        let written = file
+            .write(&buffer)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        if written != buffer.len() {
+            return Err(anyhow!("short write to {}", path.display()));
+        }
+        Ok(())
+    }
+
+    fn delete(&self, name: &CStr16, vendor: &VariableVendor) -> Result<()> {
+        let path = self.path(name, vendor);
+        clear_immutable(&path)?;
+        fs::remove_file(&path).with_context(|| format!("failed to delete {}", path.display()))
+    }
+
+    fn keys(&self) -> Result<Vec<(CString16, VariableVendor)>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("failed to list {}", self.dir.display()))?
+        {
+            let file_name = entry?.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            let Some(split) = file_name.len().checked_sub(VENDOR_SUFFIX_LEN) else {
+                continue;
+            };
+            let (name, vendor) = file_name.split_at(split);
+            if let (Ok(name), Ok(guid)) = (CString16::try_from(name), Guid::try_parse(&vendor[1..]))
+            {
+                names.push((name, VariableVendor(guid)));
+            }
+        }
+        Ok(names)
+    }
+}
+
+// Directories that aren't efivarfs may not support file flags at all
+fn clear_immutable(path: &Path) -> Result<()> {
+    let file =
+        fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut flags: libc::c_int = 0;
+    // Safety: both ioctls take a pointer to an int
+    if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return Ok(());
+    }
+    if flags & FS_IMMUTABLE_FL == 0 {
+        return Ok(());
+    }
+    flags &= !FS_IMMUTABLE_FL;
+    if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_SETFLAGS, &flags) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to make {} mutable", path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boot_vars::{EfiBootManager, LoadOptionType, LOAD_OPTION_VAR_ATTRIBUTES};
+    use uefi::cstr16;
+
+    // A fresh directory standing in for efivarfs
+    fn fake_efivarfs(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bootmgr-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn variables_are_files_with_an_attribute_prefix() {
+        let dir = fake_efivarfs("files");
+        let vars = Efivarfs::new(&dir);
+        let vendor = VariableVendor::GLOBAL_VARIABLE;
+        vars.set(
+            cstr16!("Timeout"),
+            &vendor,
+            LOAD_OPTION_VAR_ATTRIBUTES,
+            &[5, 0],
+        )
+        .unwrap();
+        // rewriting replaces the whole file
+        vars.set(
+            cstr16!("Timeout"),
+            &vendor,
+            LOAD_OPTION_VAR_ATTRIBUTES,
+            &[3],
+        )
+        .unwrap();
+
+        let raw = fs::read(dir.join("Timeout-8be4df61-93ca-11d2-aa0d-00e098032b8c")).unwrap();
+        assert_eq!(raw, [7, 0, 0, 0, 3]);
+        let (data, attributes) = vars.get(cstr16!("Timeout"), &vendor).unwrap().unwrap();
+        assert_eq!(data, [3]);
+        assert_eq!(attributes, LOAD_OPTION_VAR_ATTRIBUTES);
+
+        // files that aren't variables are skipped
+        fs::write(dir.join("README"), "not a variable").unwrap();
+        let keys = vars.keys().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].0.to_string(), "Timeout");
+        assert_eq!(keys[0].1, vendor);
+
+        vars.delete(cstr16!("Timeout"), &vendor).unwrap();
+        assert!(vars.get(cstr16!("Timeout"), &vendor).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn boot_manager_reads_a_dump() {
+        let dir = fake_efivarfs("dump");
+        // Boot0001: active, "Linux", end-of-path only; BootOrder 0001
+        let mut option = std::vec![7, 0, 0, 0, 1, 0, 0, 0, 4, 0];
+        option.extend("Linux\0".encode_utf16().flat_map(u16::to_le_bytes));
+        option.extend([0x7f, 0xff, 4, 0]);
+        let global = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+        fs::write(dir.join(format!("Boot0001-{}", global)), option).unwrap();
+        fs::write(
+            dir.join(format!("BootOrder-{}", global)),
+            [7, 0, 0, 0, 1, 0],
+        )
+        .unwrap();
+
+        let vars = Efivarfs::new(&dir);
+        let manager = EfiBootManager::new_from_variables(&vars, LoadOptionType::Boot).unwrap();
+        let ordered = manager.ordered_options();
+        assert_eq!(ordered.len(), 1);
+        assert_eq!(ordered[0].0, 1);
+        assert_eq!(ordered[0].1.description.to_string(), "Linux");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+extern crate alloc;
+
+use anyhow::Result;
+use uefi::{cstr16, table::runtime::VariableVendor, CStr16};
+
+use crate::{boot_vars::LOAD_OPTION_VAR_ATTRIBUTES, var_store::VariableStore};
+
+const TIMEOUT_VAR_NAME: &CStr16 = cstr16!("Timeout");
+const OS_INDICATIONS_VAR_NAME: &CStr16 = cstr16!("OsIndications");
+const OS_INDICATIONS_SUPPORTED_VAR_NAME: &CStr16 = cstr16!("OsIndicationsSupported");
+const BOOT_NEXT_VAR_NAME: &CStr16 = cstr16!("BootNext");
+const BOOT_CURRENT_VAR_NAME: &CStr16 = cstr16!("BootCurrent");
+
+// OsIndications bit asking the firmware to stop in its setup UI on next boot
+pub const EFI_OS_INDICATIONS_BOOT_TO_FW_UI: u64 = 0x0000_0000_0000_0001;
+
+// Fixed size global variable, None if it doesn't exist
+fn read_global_var<const N: usize>(
+    store: &dyn VariableStore,
+    name: &CStr16,
+) -> Result<Option<[u8; N]>> {
+    match store.get(name, &VariableVendor::GLOBAL_VARIABLE)? {
+        Some((value, _)) => value
+            .try_into()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("{} has an unexpected size", name)),
+        None => Ok(None),
+    }
+}
+
+// Seconds the boot manager waits before booting the default entry,
+// 0xFFFF means wait for the user
+pub fn read_timeout(store: &dyn VariableStore) -> Result<Option<u16>> {
+    Ok(read_global_var::<2>(store, TIMEOUT_VAR_NAME)?.map(u16::from_le_bytes))
+}
+
+pub fn read_os_indications_supported(store: &dyn VariableStore) -> Result<u64> {
+    Ok(
+        read_global_var::<8>(store, OS_INDICATIONS_SUPPORTED_VAR_NAME)?
+            .map(u64::from_le_bytes)
+            .unwrap_or_default(),
+    )
+}
+
+// Add `bits` to OsIndications, keeping whatever else is already requested
+pub fn set_os_indications(store: &dyn VariableStore, bits: u64) -> Result<()> {
+    let current = read_global_var::<8>(store, OS_INDICATIONS_VAR_NAME)?
+        .map(u64::from_le_bytes)
+        .unwrap_or_default();
+    store.set(
+        OS_INDICATIONS_VAR_NAME,
+        &VariableVendor::GLOBAL_VARIABLE,
+        LOAD_OPTION_VAR_ATTRIBUTES,
+        &(current | bits).to_le_bytes(),
+    )
+}
+
+// Boot#### entry the firmware boots once on the next boot
+pub fn read_boot_next(store: &dyn VariableStore) -> Result<Option<u16>> {
+    Ok(read_global_var::<2>(store, BOOT_NEXT_VAR_NAME)?.map(u16::from_le_bytes))
+}
+
+// Boot#### entry the firmware started this boot with
+pub fn read_boot_current(store: &dyn VariableStore) -> Result<Option<u16>> {
+    Ok(read_global_var::<2>(store, BOOT_CURRENT_VAR_NAME)?.map(u16::from_le_bytes))
+}
+
+pub fn set_boot_next(store: &dyn VariableStore, index: u16) -> Result<()> {
+    store.set(
+        BOOT_NEXT_VAR_NAME,
+        &VariableVendor::GLOBAL_VARIABLE,
+        LOAD_OPTION_VAR_ATTRIBUTES,
+        &index.to_le_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::var_store::MemoryStore;
+
+    #[test]
+    fn timeout_must_be_two_bytes() {
+        let store = MemoryStore::new();
+        assert_eq!(read_timeout(&store).unwrap(), None);
+        let set = |data: &[u8]| {
+            store
+                .set(
+                    TIMEOUT_VAR_NAME,
+                    &VariableVendor::GLOBAL_VARIABLE,
+                    LOAD_OPTION_VAR_ATTRIBUTES,
+                    data,
+                )
+                .unwrap()
+        };
+        set(&5u16.to_le_bytes());
+        assert_eq!(read_timeout(&store).unwrap(), Some(5));
+        set(&[5]);
+        assert!(read_timeout(&store).is_err());
+    }
+
+    #[test]
+    fn os_indications_keep_other_bits() {
+        let store = MemoryStore::new();
+        set_os_indications(&store, 0x4).unwrap();
+        set_os_indications(&store, EFI_OS_INDICATIONS_BOOT_TO_FW_UI).unwrap();
+        let (data, _) = store
+            .get(OS_INDICATIONS_VAR_NAME, &VariableVendor::GLOBAL_VARIABLE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data, 0x5u64.to_le_bytes());
+    }
+}
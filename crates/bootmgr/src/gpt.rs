@@ -0,0 +1,510 @@
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use uefi::{guid, Guid};
+
+use crate::crc32::crc32;
+
+pub const EFI_SYSTEM_PARTITION_GUID: Guid = guid!("c12a7328-f81f-11d2-ba4b-00a0c93ec93b");
+// Boot Loader Spec extended boot loader partition
+pub const XBOOTLDR_PARTITION_GUID: Guid = guid!("bc13c2ff-59e6-4262-a352-b275fd6f7172");
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_ENTRY_MIN_SIZE: usize = 128;
+const MBR_OS_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+// refuse to allocate more than this for the partition entry array
+const GPT_ENTRIES_MAX_BYTES: usize = 1024 * 1024;
+
+// Source of raw disk bytes. Offsets are in bytes from the start of the disk
+pub trait BlockReader {
+    fn block_size(&self) -> u32;
+    fn last_block(&self) -> u64;
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GptHeaderStatus {
+    Valid,
+    BadSignature,
+    BadHeaderSize,
+    BadHeaderCrc,
+    BadLocation,
+    BadEntries,
+    BadEntriesCrc,
+    ReadError,
+}
+
+#[derive(Debug, Clone)]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    pub my_lba: u64,
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub partition_entry_lba: u64,
+    pub num_partition_entries: u32,
+    pub size_of_partition_entry: u32,
+    pub partition_entry_array_crc32: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct GptPartition {
+    // 1-based, same numbering as the HardDrive device path node
+    pub number: u32,
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub starting_lba: u64,
+    pub ending_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptPartition {
+    pub fn num_blocks(&self) -> u64 {
+        self.ending_lba
+            .saturating_add(1)
+            .saturating_sub(self.starting_lba)
+    }
+
+    pub fn is_esp(&self) -> bool {
+        self.type_guid == EFI_SYSTEM_PARTITION_GUID
+    }
+
+    pub fn is_xbootldr(&self) -> bool {
+        self.type_guid == XBOOTLDR_PARTITION_GUID
+    }
+}
+
+#[derive(Debug)]
+pub struct GptDisk {
+    pub block_size: u32,
+    pub protective_mbr: bool,
+    pub primary_status: GptHeaderStatus,
+    pub backup_status: GptHeaderStatus,
+    // header the partition list was taken from
+    pub header: GptHeader,
+    pub partitions: Vec<GptPartition>,
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn guid_at(data: &[u8], offset: usize) -> Guid {
+    Guid::from_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+fn parse_partition_name(data: &[u8]) -> String {
+    let units = (0..data.len() / 2)
+        .map(|i| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]))
+        .take_while(|c| *c != 0);
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn has_protective_mbr(reader: &impl BlockReader) -> Result<bool> {
+    let mut mbr = [0u8; 512];
+    reader.read_at(0, &mut mbr)?;
+    if mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return Ok(false);
+    }
+    // four 16-byte partition records starting at offset 446, OS type at +4
+    Ok((0..4).any(|i| mbr[446 + i * 16 + 4] == MBR_OS_TYPE_GPT_PROTECTIVE))
+}
+
+fn parse_header(data: &[u8], lba: u64) -> core::result::Result<GptHeader, GptHeaderStatus> {
+    if &data[0..8] != GPT_SIGNATURE {
+        return Err(GptHeaderStatus::BadSignature);
+    }
+    let header_size = u32_at(data, 12);
+    if (header_size as usize) < GPT_HEADER_MIN_SIZE || header_size as usize > data.len() {
+        return Err(GptHeaderStatus::BadHeaderSize);
+    }
+
+    // CRC is calculated with the CRC field itself zeroed
+    let mut header_bytes = data[..header_size as usize].to_vec();
+    header_bytes[16..20].fill(0);
+    if crc32(&header_bytes) != u32_at(data, 16) {
+        return Err(GptHeaderStatus::BadHeaderCrc);
+    }
+
+    let header = GptHeader {
+        revision: u32_at(data, 8),
+        header_size,
+        my_lba: u64_at(data, 24),
+        alternate_lba: u64_at(data, 32),
+        first_usable_lba: u64_at(data, 40),
+        last_usable_lba: u64_at(data, 48),
+        disk_guid: guid_at(data, 56),
+        partition_entry_lba: u64_at(data, 72),
+        num_partition_entries: u32_at(data, 80),
+        size_of_partition_entry: u32_at(data, 84),
+        partition_entry_array_crc32: u32_at(data, 88),
+    };
+    if header.my_lba != lba {
+        return Err(GptHeaderStatus::BadLocation);
+    }
+    Ok(header)
+}
+
+fn read_partitions(
+    reader: &impl BlockReader,
+    header: &GptHeader,
+) -> core::result::Result<Vec<GptPartition>, GptHeaderStatus> {
+    let entry_size = header.size_of_partition_entry as usize;
+    if entry_size < GPT_ENTRY_MIN_SIZE || !entry_size.is_power_of_two() {
+        return Err(GptHeaderStatus::BadEntries);
+    }
+    let array_size = entry_size
+        .checked_mul(header.num_partition_entries as usize)
+        .filter(|s| *s <= GPT_ENTRIES_MAX_BYTES)
+        .ok_or(GptHeaderStatus::BadEntries)?;
+
+    let offset = header
+        .partition_entry_lba
+        .checked_mul(reader.block_size() as u64)
+        .ok_or(GptHeaderStatus::BadEntries)?;
+    let mut entries = vec![0u8; array_size];
+    reader
+        .read_at(offset, &mut entries)
+        .map_err(|_| GptHeaderStatus::ReadError)?;
+    if crc32(&entries) != header.partition_entry_array_crc32 {
+        return Err(GptHeaderStatus::BadEntriesCrc);
+    }
+
+    Ok(entries
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter(|(_, e)| !guid_at(e, 0).is_zero())
+        .map(|(i, e)| GptPartition {
+            number: i as u32 + 1,
+            type_guid: guid_at(e, 0),
+            unique_guid: guid_at(e, 16),
+            starting_lba: u64_at(e, 32),
+            ending_lba: u64_at(e, 40),
+            attributes: u64_at(e, 48),
+            name: parse_partition_name(&e[56..128]),
+        })
+        .collect())
+}
+
+fn read_header_and_partitions(
+    reader: &impl BlockReader,
+    lba: u64,
+) -> core::result::Result<(GptHeader, Vec<GptPartition>), GptHeaderStatus> {
+    let block_size = reader.block_size() as usize;
+    // a corrupted primary can name any backup LBA
+    let offset = lba
+        .checked_mul(block_size as u64)
+        .ok_or(GptHeaderStatus::BadLocation)?;
+    let mut data = vec![0u8; block_size.max(GPT_HEADER_MIN_SIZE)];
+    reader
+        .read_at(offset, &mut data)
+        .map_err(|_| GptHeaderStatus::ReadError)?;
+    let header = parse_header(&data, lba)?;
+    let partitions = read_partitions(reader, &header)?;
+    Ok((header, partitions))
+}
+
+impl GptDisk {
+    pub fn read(reader: &impl BlockReader) -> Result<Self> {
+        let protective_mbr = has_protective_mbr(reader)?;
+
+        let primary = read_header_and_partitions(reader, 1);
+        // trust primary's idea of where the backup is, fall back to the last block
+        let backup_lba = match &primary {
+            Ok((h, _)) => h.alternate_lba,
+            Err(_) => reader.last_block(),
+        };
+        let backup = read_header_and_partitions(reader, backup_lba);
+
+        let primary_status = primary
+            .as_ref()
+            .err()
+            .copied()
+            .unwrap_or(GptHeaderStatus::Valid);
+        let backup_status = backup
+            .as_ref()
+            .err()
+            .copied()
+            .unwrap_or(GptHeaderStatus::Valid);
+
+        let (header, partitions) = primary.or(backup).map_err(|_| {
+            anyhow!(
+                "no valid GPT found (primary: {:?}, backup: {:?})",
+                primary_status,
+                backup_status
+            )
+        })?;
+
+        Ok(GptDisk {
+            block_size: reader.block_size(),
+            protective_mbr,
+            primary_status,
+            backup_status,
+            header,
+            partitions,
+        })
+    }
+
+    // primary header or its entries are damaged but the backup copy is usable
+    pub fn is_primary_corrupted(&self) -> bool {
+        self.primary_status != GptHeaderStatus::Valid
+            && self.backup_status == GptHeaderStatus::Valid
+    }
+
+    pub fn find_by_type(&self, type_guid: Guid) -> impl Iterator<Item = &GptPartition> {
+        self.partitions
+            .iter()
+            .filter(move |p| p.type_guid == type_guid)
+    }
+
+    pub fn esps(&self) -> impl Iterator<Item = &GptPartition> {
+        self.partitions.iter().filter(|p| p.is_esp())
+    }
+
+    pub fn xbootldrs(&self) -> impl Iterator<Item = &GptPartition> {
+        self.partitions.iter().filter(|p| p.is_xbootldr())
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<&GptPartition> {
+        self.partitions.iter().find(|p| p.name == name)
+    }
+
+    pub fn find_by_unique_guid(&self, guid: &Guid) -> Option<&GptPartition> {
+        self.partitions.iter().find(|p| p.unique_guid == *guid)
+    }
+
+    pub fn log(&self) {
+        info!(
+            "GPT disk {} (block size {}, protective MBR: {})",
+            self.header.disk_guid, self.block_size, self.protective_mbr
+        );
+        if self.is_primary_corrupted() {
+            warn!(
+                "Primary GPT is corrupted ({:?}), using backup at LBA {}",
+                self.primary_status, self.header.my_lba
+            );
+        } else if self.backup_status != GptHeaderStatus::Valid {
+            warn!("Backup GPT is corrupted ({:?})", self.backup_status);
+        }
+        for p in self.partitions.iter() {
+            info!(
+                "  #{} '{}' type {} uuid {} LBA {}..{} attr {:#x}",
+                p.number,
+                p.name,
+                p.type_guid,
+                p.unique_guid,
+                p.starting_lba,
+                p.ending_lba,
+                p.attributes
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: usize = 512;
+    const DISK_BLOCKS: u64 = 16;
+    const ENTRIES: u32 = 4;
+    const LINUX_DATA_GUID: Guid = guid!("0fc63daf-8483-4772-8e79-3d69d8477de4");
+    const ESP_UUID: Guid = guid!("6a1f4e2b-3c5d-4e7f-8091-a2b3c4d5e6f7");
+    const ROOT_UUID: Guid = guid!("1b2c3d4e-5f60-4718-9a2b-3c4d5e6f7a8b");
+
+    struct MemoryDisk(Vec<u8>);
+
+    impl BlockReader for MemoryDisk {
+        fn block_size(&self) -> u32 {
+            BLOCK_SIZE as u32
+        }
+
+        fn last_block(&self) -> u64 {
+            (self.0.len() / BLOCK_SIZE) as u64 - 1
+        }
+
+        fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+            let start = usize::try_from(offset)?;
+            let data = start
+                .checked_add(buffer.len())
+                .and_then(|end| self.0.get(start..end))
+                .ok_or_else(|| anyhow!("read past the end of the disk"))?;
+            buffer.copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    impl MemoryDisk {
+        fn block(&mut self, lba: u64) -> &mut [u8] {
+            let start = lba as usize * BLOCK_SIZE;
+            &mut self.0[start..start + BLOCK_SIZE]
+        }
+
+        // Change a header field and fix the header CRC up again
+        fn patch_header(&mut self, lba: u64, offset: usize, value: &[u8]) {
+            self.block(lba)[offset..offset + value.len()].copy_from_slice(value);
+            self.update_header_crc(lba);
+        }
+
+        fn update_header_crc(&mut self, lba: u64) {
+            let header = self.block(lba);
+            header[16..20].fill(0);
+            let crc = crc32(&header[..GPT_HEADER_MIN_SIZE]);
+            header[16..20].copy_from_slice(&crc.to_le_bytes());
+        }
+    }
+
+    fn entry(type_guid: Guid, unique_guid: Guid, first: u64, last: u64, name: &str) -> Vec<u8> {
+        let mut entry = vec![0u8; GPT_ENTRY_MIN_SIZE];
+        entry[0..16].copy_from_slice(&type_guid.to_bytes());
+        entry[16..32].copy_from_slice(&unique_guid.to_bytes());
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+        for (i, unit) in name.encode_utf16().enumerate() {
+            entry[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entry
+    }
+
+    // Protective MBR, primary header at LBA 1 with its entries at LBA 2,
+    // backup entries and header in the last two blocks
+    fn disk() -> MemoryDisk {
+        let mut entries = entry(EFI_SYSTEM_PARTITION_GUID, ESP_UUID, 3, 8, "EFI System");
+        entries.extend(vec![0u8; GPT_ENTRY_MIN_SIZE]);
+        entries.extend(entry(LINUX_DATA_GUID, ROOT_UUID, 9, 13, "root"));
+        entries.extend(vec![0u8; GPT_ENTRY_MIN_SIZE]);
+        let entries_crc = crc32(&entries);
+
+        let mut disk = MemoryDisk(vec![0u8; DISK_BLOCKS as usize * BLOCK_SIZE]);
+        let mbr = disk.block(0);
+        mbr[446 + 4] = MBR_OS_TYPE_GPT_PROTECTIVE;
+        mbr[510] = 0x55;
+        mbr[511] = 0xAA;
+
+        let last = DISK_BLOCKS - 1;
+        for (my_lba, alternate_lba, entries_lba) in [(1, last, 2), (last, 1, last - 1)] {
+            disk.block(entries_lba)[..entries.len()].copy_from_slice(&entries);
+            let header = disk.block(my_lba);
+            header[0..8].copy_from_slice(GPT_SIGNATURE);
+            header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+            header[12..16].copy_from_slice(&(GPT_HEADER_MIN_SIZE as u32).to_le_bytes());
+            header[24..32].copy_from_slice(&my_lba.to_le_bytes());
+            header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+            header[40..48].copy_from_slice(&3u64.to_le_bytes());
+            header[48..56].copy_from_slice(&13u64.to_le_bytes());
+            header[56..72]
+                .copy_from_slice(&guid!("00112233-4455-6677-8899-aabbccddeeff").to_bytes());
+            header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+            header[80..84].copy_from_slice(&ENTRIES.to_le_bytes());
+            header[84..88].copy_from_slice(&(GPT_ENTRY_MIN_SIZE as u32).to_le_bytes());
+            header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+            disk.update_header_crc(my_lba);
+        }
+        disk
+    }
+
+    #[test]
+    fn valid_gpt() {
+        let gpt = GptDisk::read(&disk()).unwrap();
+        assert!(gpt.protective_mbr);
+        assert_eq!(gpt.primary_status, GptHeaderStatus::Valid);
+        assert_eq!(gpt.backup_status, GptHeaderStatus::Valid);
+        assert_eq!(gpt.header.my_lba, 1);
+        // empty entries are skipped but keep their number
+        let numbers: Vec<u32> = gpt.partitions.iter().map(|p| p.number).collect();
+        assert_eq!(numbers, [1, 3]);
+        let esp = gpt.esps().next().unwrap();
+        assert_eq!(esp.name, "EFI System");
+        assert_eq!(esp.num_blocks(), 6);
+        assert_eq!(gpt.find_by_name("root").unwrap().unique_guid, ROOT_UUID);
+        assert_eq!(gpt.find_by_unique_guid(&ESP_UUID).unwrap().number, 1);
+    }
+
+    #[test]
+    fn corrupted_primary_uses_backup() {
+        let mut disk = disk();
+        // a changed header without a matching CRC
+        disk.block(1)[48] ^= 0xff;
+        let gpt = GptDisk::read(&disk).unwrap();
+        assert_eq!(gpt.primary_status, GptHeaderStatus::BadHeaderCrc);
+        assert!(gpt.is_primary_corrupted());
+        assert_eq!(gpt.header.my_lba, DISK_BLOCKS - 1);
+        assert_eq!(gpt.partitions.len(), 2);
+
+        disk.block(DISK_BLOCKS - 1)[0] = b'X';
+        let e = GptDisk::read(&disk).unwrap_err().to_string();
+        assert!(
+            e.contains("BadHeaderCrc") && e.contains("BadSignature"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn bad_entry_array_crc() {
+        let mut disk = disk();
+        disk.block(2)[56] = b'e';
+        let gpt = GptDisk::read(&disk).unwrap();
+        assert_eq!(gpt.primary_status, GptHeaderStatus::BadEntriesCrc);
+        assert_eq!(gpt.backup_status, GptHeaderStatus::Valid);
+        assert_eq!(
+            gpt.find_by_unique_guid(&ESP_UUID).unwrap().name,
+            "EFI System"
+        );
+    }
+
+    #[test]
+    fn absurd_entry_counts_and_sizes() {
+        let cases: [(usize, &[u8]); 5] = [
+            // too many entries
+            (80, &u32::MAX.to_le_bytes()),
+            // entries too small, not a power of two, or huge
+            (84, &64u32.to_le_bytes()),
+            (84, &129u32.to_le_bytes()),
+            (84, &0x8000_0000u32.to_le_bytes()),
+            // the entry array's offset doesn't fit in 64 bits
+            (72, &u64::MAX.to_le_bytes()),
+        ];
+        for (offset, value) in cases {
+            let mut disk = disk();
+            disk.patch_header(1, offset, value);
+            let gpt = GptDisk::read(&disk).unwrap();
+            assert_eq!(gpt.primary_status, GptHeaderStatus::BadEntries);
+            assert_eq!(gpt.header.my_lba, DISK_BLOCKS - 1);
+        }
+    }
+
+    #[test]
+    fn absurd_backup_location() {
+        let mut disk = disk();
+        disk.patch_header(1, 32, &u64::MAX.to_le_bytes());
+        let gpt = GptDisk::read(&disk).unwrap();
+        assert_eq!(gpt.primary_status, GptHeaderStatus::Valid);
+        assert_eq!(gpt.backup_status, GptHeaderStatus::BadLocation);
+
+        // an LBA that fits but is past the end can't be read
+        disk.patch_header(1, 32, &DISK_BLOCKS.to_le_bytes());
+        let gpt = GptDisk::read(&disk).unwrap();
+        assert_eq!(gpt.backup_status, GptHeaderStatus::ReadError);
+    }
+
+    #[test]
+    fn header_must_be_where_it_says() {
+        let mut disk = disk();
+        disk.patch_header(1, 24, &5u64.to_le_bytes());
+        let gpt = GptDisk::read(&disk).unwrap();
+        assert_eq!(gpt.primary_status, GptHeaderStatus::BadLocation);
+    }
+}
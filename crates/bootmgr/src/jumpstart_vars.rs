@@ -0,0 +1,190 @@
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, Result};
+use uefi::{
+    cstr16, guid,
+    table::runtime::{VariableAttributes, VariableVendor},
+    CStr16,
+};
+
+use crate::{crc32::crc32, var_store::VariableStore};
+
+// Vendor GUID for all variables owned by jumpstart
+pub const JUMPSTART_VENDOR: VariableVendor =
+    VariableVendor(guid!("4a756d70-7374-6172-8a1e-b7c3d2e9f051"));
+
+// Non-volatile and visible at runtime so the OS can read and change them
+pub const JUMPSTART_VAR_ATTRIBUTES: VariableAttributes = VariableAttributes::NON_VOLATILE
+    .union(VariableAttributes::BOOTSERVICE_ACCESS)
+    .union(VariableAttributes::RUNTIME_ACCESS);
+
+// Binary load options: magic, format version, then the command line as UTF-8.
+// Lets tools that write Boot#### entries skip the UCS-2 encoding
+pub const LOAD_OPTIONS_MAGIC: &[u8; 4] = b"JSLO";
+const LOAD_OPTIONS_VERSION: u8 = 1;
+
+// Config variables hold config file text so the OS can change settings
+// without mounting the ESP. Layout (version 1):
+//   magic, u8 version, u8[3] reserved, u32 CRC32 of the text, UTF-8 text
+// JumpstartConfig is written by the OS; the last copy of it that parsed is
+// kept in JumpstartConfigGood and used when it doesn't. JumpstartConfigOnce
+// applies to one boot only, jumpstart deletes it when read. Only a
+// non-volatile one can be set from the OS.
+pub const CONFIG_VAR_NAME: &CStr16 = cstr16!("JumpstartConfig");
+pub const CONFIG_GOOD_VAR_NAME: &CStr16 = cstr16!("JumpstartConfigGood");
+pub const CONFIG_ONCE_VAR_NAME: &CStr16 = cstr16!("JumpstartConfigOnce");
+pub const CONFIG_VAR_MAGIC: &[u8; 4] = b"JSCF";
+const CONFIG_VAR_VERSION: u8 = 1;
+const CONFIG_VAR_HEADER_SIZE: usize = 12;
+
+// Read a jumpstart variable, None if it doesn't exist
+pub fn read_jumpstart_var(store: &dyn VariableStore, name: &CStr16) -> Result<Option<Box<[u8]>>> {
+    Ok(store
+        .get(name, &JUMPSTART_VENDOR)?
+        .map(|(value, _)| value.into_boxed_slice()))
+}
+
+pub fn write_jumpstart_var(store: &dyn VariableStore, name: &CStr16, data: &[u8]) -> Result<()> {
+    store.set(name, &JUMPSTART_VENDOR, JUMPSTART_VAR_ATTRIBUTES, data)
+}
+
+pub fn delete_jumpstart_var(store: &dyn VariableStore, name: &CStr16) -> Result<()> {
+    store.delete(name, &JUMPSTART_VENDOR)
+}
+
+// Load options in the binary format, None if `data` isn't in it
+pub fn decode_load_options(data: &[u8]) -> Result<Option<String>> {
+    let Some(rest) = data.strip_prefix(LOAD_OPTIONS_MAGIC) else {
+        return Ok(None);
+    };
+    match rest.split_first() {
+        Some((&LOAD_OPTIONS_VERSION, text)) => core::str::from_utf8(text)
+            .map(|s| Some(s.to_string()))
+            .map_err(|_| anyhow!("binary load options are not UTF-8")),
+        Some((version, _)) => Err(anyhow!("unsupported load options version {}", version)),
+        None => Err(anyhow!("binary load options too short")),
+    }
+}
+
+pub fn encode_load_options(text: &str) -> Vec<u8> {
+    let mut data = LOAD_OPTIONS_MAGIC.to_vec();
+    data.push(LOAD_OPTIONS_VERSION);
+    data.extend_from_slice(text.as_bytes());
+    data
+}
+
+// Load options word that marks a companion Boot#### entry written by sync
+// mode. Other entries may carry binary load options too, e.g. from
+// `jsctl create --options`, and must never be touched by sync
+pub const COMPANION_MARKER: &str = "companion=1";
+
+pub fn is_companion_options(data: &[u8]) -> bool {
+    matches!(decode_load_options(data), Ok(Some(text))
+        if text.split_whitespace().any(|word| word == COMPANION_MARKER))
+}
+
+pub fn decode_config_var(data: &[u8]) -> Result<String> {
+    if data.len() < CONFIG_VAR_HEADER_SIZE || !data.starts_with(CONFIG_VAR_MAGIC) {
+        return Err(anyhow!("not a jumpstart config variable"));
+    }
+    if data[4] != CONFIG_VAR_VERSION {
+        return Err(anyhow!("unsupported config variable version {}", data[4]));
+    }
+    let crc = u32::from_le_bytes(data[8..12].try_into().unwrap());
+    let text = &data[CONFIG_VAR_HEADER_SIZE..];
+    if crc32(text) != crc {
+        return Err(anyhow!("config variable CRC mismatch"));
+    }
+    core::str::from_utf8(text)
+        .map(|s| s.to_string())
+        .map_err(|_| anyhow!("config variable is not UTF-8"))
+}
+
+pub fn encode_config_var(text: &str) -> Vec<u8> {
+    let mut data = CONFIG_VAR_MAGIC.to_vec();
+    data.extend_from_slice(&[CONFIG_VAR_VERSION, 0, 0, 0]);
+    data.extend_from_slice(&crc32(text.as_bytes()).to_le_bytes());
+    data.extend_from_slice(text.as_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::var_store::MemoryStore;
+    use alloc::format;
+
+    #[test]
+    fn config_var_round_trip() {
+        let text = "timeout=3\n[model product=PowerEdge*]\nconnect=driver\n";
+        let data = encode_config_var(text);
+        assert!(data.starts_with(CONFIG_VAR_MAGIC));
+        assert_eq!(decode_config_var(&data).unwrap(), text);
+    }
+
+    #[test]
+    fn config_var_corruption_is_detected() {
+        let mut data = encode_config_var("timeout=3");
+        *data.last_mut().unwrap() = b'4';
+        assert!(decode_config_var(&data).is_err());
+
+        let mut data = encode_config_var("timeout=3");
+        data[4] = CONFIG_VAR_VERSION + 1;
+        assert!(decode_config_var(&data).is_err());
+
+        assert!(decode_config_var(b"timeout=3").is_err());
+        assert!(decode_config_var(CONFIG_VAR_MAGIC).is_err());
+    }
+
+    #[test]
+    fn load_options_round_trip() {
+        let data = encode_load_options("target=partlabel=ESP-A:\\EFI\\x.efi menu");
+        assert_eq!(
+            decode_load_options(&data).unwrap().as_deref(),
+            Some("target=partlabel=ESP-A:\\EFI\\x.efi menu")
+        );
+        // anything else is for someone else to decode
+        assert_eq!(decode_load_options(b"m\0e\0n\0u\0\0\0").unwrap(), None);
+        assert!(decode_load_options(LOAD_OPTIONS_MAGIC).is_err());
+    }
+
+    #[test]
+    fn only_marked_options_are_companions() {
+        let companion = format!("{} target=partuuid=x:\\EFI\\x.efi", COMPANION_MARKER);
+        assert!(is_companion_options(&encode_load_options(&companion)));
+        // an operator entry with binary load options
+        assert!(!is_companion_options(&encode_load_options(
+            "target=partuuid=x:\\EFI\\x.efi"
+        )));
+        assert!(!is_companion_options(&encode_load_options("companion=10")));
+        assert!(!is_companion_options(COMPANION_MARKER.as_bytes()));
+    }
+
+    #[test]
+    fn jumpstart_vars_use_the_jumpstart_vendor() {
+        let store = MemoryStore::new();
+        write_jumpstart_var(&store, CONFIG_VAR_NAME, b"data").unwrap();
+        let (data, attributes) = store
+            .get(CONFIG_VAR_NAME, &JUMPSTART_VENDOR)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data, b"data");
+        assert_eq!(attributes, JUMPSTART_VAR_ATTRIBUTES);
+        assert_eq!(
+            read_jumpstart_var(&store, CONFIG_VAR_NAME)
+                .unwrap()
+                .as_deref(),
+            Some(&b"data"[..])
+        );
+
+        delete_jumpstart_var(&store, CONFIG_VAR_NAME).unwrap();
+        assert!(read_jumpstart_var(&store, CONFIG_VAR_NAME)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,23 @@
+#![cfg_attr(not(test), no_std)]
+
+// Boot manager variables: load options, their order and jumpstart's own
+// variables, and the Boot#### scan deciding what to boot. Shared by
+// jumpstart, the Linux side tools and host tests, so nothing here may depend
+// on boot services
+extern crate alloc;
+
+pub mod bls;
+pub mod boot_vars;
+pub mod config;
+pub mod crc32;
+pub mod crypto;
+pub mod device_path;
+#[cfg(feature = "efivarfs")]
+pub mod efivarfs;
+pub mod global_vars;
+pub mod gpt;
+pub mod jumpstart_vars;
+pub mod scan;
+pub mod slots;
+pub mod smbios;
+pub mod var_store;
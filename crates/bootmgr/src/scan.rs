@@ -0,0 +1,751 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use uefi::proto::device_path::DevicePath;
+
+use crate::{
+    boot_vars::{device_path_text, EfiBootManager, EfiLoadOption},
+    device_path::{append_file_path, with_default_loader, DevicePathExt, PartialEqExt},
+};
+
+// The Boot#### scan: every Boot#### entry is matched against the NVMe
+// filesystems the firmware couldn't see before our driver was connected, and
+// the matches are booted in turn. Everything that needs boot services is
+// behind `Firmware`, so the decisions can be tested off-target
+
+// What the scan needs from the firmware
+pub trait Firmware {
+    // A loaded, not yet started image
+    type Image;
+
+    // How a candidate was selected, for whatever has to happen around
+    // loading and starting it
+    type Selection;
+
+    // Connect the controllers to the loaded drivers
+    fn connect_drivers(&self) -> Result<()>;
+
+    // Device paths of all filesystems
+    fn filesystems(&self) -> Result<Vec<Box<DevicePath>>>;
+
+    // Read, verify and load a candidate's image without starting it
+    fn load_image(&self, candidate: &Candidate<Self::Selection>) -> Result<Self::Image>;
+
+    // Start a loaded image. Only returns if the image exited
+    fn start_image(&self, image: Self::Image, candidate: &Candidate<Self::Selection>)
+        -> Result<()>;
+}
+
+// An image the boot would try
+pub struct Candidate<S> {
+    pub source: String,
+    pub device_path: Box<DevicePath>,
+    pub selection: S,
+}
+
+// Connect the drivers, then find the filesystems on NVMe namespaces
+pub fn discover_nvme_filesystems<F: Firmware>(firmware: &F) -> Result<Vec<Box<DevicePath>>> {
+    firmware.connect_drivers()?;
+    Ok(firmware
+        .filesystems()?
+        .into_iter()
+        .filter(|p| p.is_nvme())
+        .collect())
+}
+
+// Full device paths of a Boot#### entry on the NVMe filesystems: the
+// filesystem with the entry's HardDrive node plus the entry's file path, or
+// the default loader if the entry stops at the partition. A cloned disk has
+// the same partitions as the original, so more than one filesystem can match
+pub fn match_nvme_boot_option(
+    boot_option: &EfiLoadOption,
+    fs_device_paths: &[Box<DevicePath>],
+) -> Result<Vec<Box<DevicePath>>> {
+    let mut has_hard_drive = false;
+    for p in boot_option.device_path_list.iter() {
+        let Some(hd) = p.hard_drive() else {
+            continue;
+        };
+        has_hard_drive = true;
+        let matches = fs_device_paths
+            .iter()
+            .filter(|nvme_path| nvme_path.hard_drive().is_some_and(|h| hd.eq(h)))
+            .map(|nvme_path| match p.file_path() {
+                Some(file_path) => append_file_path(
+                    nvme_path,
+                    &file_path
+                        .path_name()
+                        .to_cstring16()
+                        .map_err(anyhow::Error::msg)?,
+                ),
+                None => with_default_loader(nvme_path),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !matches.is_empty() {
+            return Ok(matches);
+        }
+    }
+    if has_hard_drive {
+        Err(anyhow!("partition is not on any NVMe filesystem"))
+    } else {
+        Err(anyhow!("no HardDrive node"))
+    }
+}
+
+// Candidates for the active Boot#### entries, in BootOrder order. Entries
+// that don't match an NVMe filesystem are logged and left out
+pub fn boot_option_candidates<S>(
+    boot_mgr: &EfiBootManager,
+    fs_device_paths: &[Box<DevicePath>],
+    selection: impl Fn(usize) -> S,
+) -> Vec<Candidate<S>> {
+    let mut candidates = Vec::new();
+    for (index, boot_option) in boot_mgr.ordered_options() {
+        if !boot_option.is_active() {
+            info!("Boot{:04X} skipped: inactive", index);
+            continue;
+        }
+        info!("Boot{:04X}:", index);
+        for (i, p) in boot_option.device_path_list.iter().enumerate() {
+            info!("Segment {}: '{}'", i, device_path_text(p));
+        }
+        let paths = match match_nvme_boot_option(boot_option, fs_device_paths) {
+            Ok(paths) => paths,
+            Err(e) => {
+                info!("Boot{:04X} skipped: {:?}", index, e);
+                continue;
+            }
+        };
+        if paths.len() > 1 {
+            warn!(
+                "Boot{:04X} matches {} NVMe filesystems, cloned disk?",
+                index,
+                paths.len()
+            );
+        }
+        candidates.extend(paths.into_iter().map(|device_path| Candidate {
+            source: format!("Boot{:04X}", index),
+            device_path,
+            selection: selection(index),
+        }));
+    }
+    candidates
+}
+
+// Whether the scan would pick the cached Boot#### entry again, decided with
+// the same NVMe match the scan uses. `filesystems` are all filesystems the
+// firmware has right now, which may not include every NVMe disk yet: an
+// earlier entry whose partition isn't on any filesystem could be on one of
+// them, so the answer is no, and the full scan decides
+pub fn is_cached_option_current(
+    boot_mgr: &EfiBootManager,
+    index: usize,
+    device_path: &DevicePath,
+    filesystems: &[Box<DevicePath>],
+) -> bool {
+    let nvme_filesystems: Vec<_> = filesystems
+        .iter()
+        .filter(|p| p.is_nvme())
+        .map(|p| p.to_boxed())
+        .collect();
+    for (option_index, option) in boot_mgr.ordered_options() {
+        if !option.is_active() {
+            continue;
+        }
+        match match_nvme_boot_option(option, &nvme_filesystems) {
+            Ok(paths) => {
+                return option_index == index && paths[0].as_bytes() == device_path.as_bytes();
+            }
+            Err(_) => {
+                let partitions: Vec<_> = option
+                    .device_path_list
+                    .iter()
+                    .filter_map(|p| p.hard_drive())
+                    .collect();
+                let elsewhere = partitions.iter().any(|hd| {
+                    filesystems
+                        .iter()
+                        .any(|fs| fs.hard_drive().is_some_and(|h| hd.eq(h)))
+                });
+                // skipped by the scan: network entries and other disks
+                if !partitions.is_empty() && !elsewhere {
+                    return false;
+                }
+            }
+        }
+    }
+    false
+}
+
+// Try the candidates in order. One that fails to load, e.g. because the
+// file is missing or doesn't verify, is skipped; so is one that exits
+pub fn boot_candidates<F: Firmware>(firmware: &F, candidates: &[Candidate<F::Selection>]) {
+    for candidate in candidates.iter() {
+        info!(
+            "Trying {}: {}",
+            candidate.source,
+            device_path_text(&candidate.device_path)
+        );
+        match firmware
+            .load_image(candidate)
+            .and_then(|image| firmware.start_image(image, candidate))
+        {
+            Ok(_) => info!("Image for {} exited", candidate.source),
+            Err(e) => info!("{} failed: {:?}", candidate.source, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boot_vars::{
+            EfiBootOrder, LoadOptionAttributes, LoadOptionAttributesBits, LoadOptionType,
+            LOAD_OPTION_VAR_ATTRIBUTES,
+        },
+        device_path::DEFAULT_LOADER,
+        global_vars::set_boot_next,
+        var_store::{MemoryStore, VariableStore},
+    };
+    use alloc::{string::ToString, vec};
+    use core::cell::{Cell, RefCell};
+    use uefi::{
+        guid,
+        proto::device_path::{
+            build::{self, DevicePathBuilder},
+            media::{PartitionFormat, PartitionSignature},
+        },
+        table::runtime::VariableVendor,
+        CString16, Guid,
+    };
+
+    const ESP: Guid = guid!("5d1c8e4a-0b2f-4e61-9c3d-7a8b9c0d1e2f");
+    const ROOT: Guid = guid!("9e2a1b3c-4d5e-4f60-8a7b-1c2d3e4f5a6b");
+    const OTHER_ESP: Guid = guid!("0c1d2e3f-4a5b-4c6d-8e7f-9a0b1c2d3e4f");
+    const LOADER: &str = r"\EFI\ubuntu\shimx64.efi";
+
+    #[derive(Clone, Copy)]
+    enum Disk {
+        Nvme(u8),
+        Sata(u8),
+    }
+
+    // [PciRoot/Pci/NVMe or SATA]/[HD]/[file]: a full filesystem or image
+    // path, or, without a disk, the short form firmware stores in Boot####
+    fn device_path(
+        disk: Option<Disk>,
+        partition: Option<Guid>,
+        file: Option<&str>,
+    ) -> Box<DevicePath> {
+        let mut buffer = Vec::new();
+        let mut builder = DevicePathBuilder::with_vec(&mut buffer);
+        if let Some(disk) = disk {
+            let pci_device = match disk {
+                Disk::Nvme(device) | Disk::Sata(device) => device,
+            };
+            builder = builder
+                .push(&build::acpi::Acpi {
+                    hid: 0x0a03_41d0,
+                    uid: 0,
+                })
+                .unwrap()
+                .push(&build::hardware::Pci {
+                    function: 0,
+                    device: pci_device,
+                })
+                .unwrap();
+            builder = match disk {
+                Disk::Nvme(_) => builder.push(&build::messaging::NvmeNamespace {
+                    namespace_identifier: pci_device.into(),
+                    ieee_extended_unique_identifier: 0,
+                }),
+                Disk::Sata(_) => builder.push(&build::messaging::Sata {
+                    hba_port_number: 0,
+                    port_multiplier_port_number: 0xffff,
+                    logical_unit_number: 0,
+                }),
+            }
+            .unwrap();
+        }
+        if let Some(partition) = partition {
+            builder = builder
+                .push(&build::media::HardDrive {
+                    partition_number: 1,
+                    partition_start: 2048,
+                    partition_size: 1_048_576,
+                    partition_signature: PartitionSignature::Guid(partition),
+                    partition_format: PartitionFormat::GPT,
+                })
+                .unwrap();
+        }
+        let file = file.map(|f| CString16::try_from(f).unwrap());
+        if let Some(file) = &file {
+            builder = builder
+                .push(&build::media::FilePath { path_name: file })
+                .unwrap();
+        }
+        builder.finalize().unwrap().to_boxed()
+    }
+
+    fn image(disk: Disk, partition: Guid) -> Box<DevicePath> {
+        device_path(Some(disk), Some(partition), Some(LOADER))
+    }
+
+    fn boot_option(description: &str, device_path: Box<DevicePath>) -> EfiLoadOption {
+        EfiLoadOption {
+            attributes: LoadOptionAttributes::from(
+                LoadOptionAttributesBits::LoadOptionActive as u32,
+            ),
+            description: CString16::try_from(description).unwrap(),
+            device_path_list: vec![device_path],
+            optional_data: None,
+        }
+    }
+
+    fn on_partition(description: &str, partition: Guid) -> EfiLoadOption {
+        boot_option(
+            description,
+            device_path(None, Some(partition), Some(LOADER)),
+        )
+    }
+
+    // Firmware following a script: filesystems on NVMe only show up once the
+    // drivers are connected, images load if they exist and aren't rejected.
+    // What happens is recorded in `events`
+    #[derive(Default)]
+    struct ScriptedFirmware {
+        disks: Vec<(Disk, Guid)>,
+        files: Vec<Box<DevicePath>>,
+        rejected: Vec<Box<DevicePath>>,
+        connected: Cell<bool>,
+        events: RefCell<Vec<String>>,
+    }
+
+    impl ScriptedFirmware {
+        fn new(disks: &[(Disk, Guid)]) -> Self {
+            ScriptedFirmware {
+                disks: disks.to_vec(),
+                // every filesystem has the loader unless a test says otherwise
+                files: disks.iter().map(|(d, p)| image(*d, *p)).collect(),
+                ..Default::default()
+            }
+        }
+
+        fn remove_file(&mut self, path: &DevicePath) {
+            self.files.retain(|f| f.as_bytes() != path.as_bytes());
+        }
+
+        fn event(&self, event: String) {
+            self.events.borrow_mut().push(event);
+        }
+    }
+
+    impl Firmware for ScriptedFirmware {
+        type Image = String;
+        type Selection = ();
+
+        fn connect_drivers(&self) -> Result<()> {
+            // connecting again changes nothing
+            if !self.connected.replace(true) {
+                self.event("connect".to_string());
+            }
+            Ok(())
+        }
+
+        fn filesystems(&self) -> Result<Vec<Box<DevicePath>>> {
+            Ok(self
+                .disks
+                .iter()
+                .filter(|(disk, _)| matches!(disk, Disk::Sata(_)) || self.connected.get())
+                .map(|(disk, partition)| device_path(Some(*disk), Some(*partition), None))
+                .collect())
+        }
+
+        fn load_image(&self, candidate: &Candidate<()>) -> Result<String> {
+            // like the real read, a path without a file loads the default loader
+            let device_path = &*with_default_loader(&candidate.device_path)?;
+            let text = device_path_text(device_path);
+            let has = |list: &[Box<DevicePath>]| {
+                list.iter().any(|p| p.as_bytes() == device_path.as_bytes())
+            };
+            if !has(&self.files) {
+                self.event(format!("missing {}", text));
+                return Err(anyhow!("not found"));
+            }
+            if has(&self.rejected) {
+                self.event(format!("rejected {}", text));
+                return Err(anyhow!("security violation"));
+            }
+            Ok(text)
+        }
+
+        fn start_image(&self, image: String, candidate: &Candidate<()>) -> Result<()> {
+            self.event(format!("start {} {}", candidate.source, image));
+            Ok(())
+        }
+    }
+
+    // Boot#### entries numbered from 0, with BootOrder if given
+    fn boot_store(boot_options: &[EfiLoadOption], order: Option<&[u16]>) -> MemoryStore {
+        let store = MemoryStore::new();
+        for (index, option) in boot_options.iter().enumerate() {
+            let name = CString16::try_from(format!("Boot{:04X}", index).as_str()).unwrap();
+            store
+                .set(
+                    &name,
+                    &VariableVendor::GLOBAL_VARIABLE,
+                    LOAD_OPTION_VAR_ATTRIBUTES,
+                    &Vec::from(option),
+                )
+                .unwrap();
+        }
+        if let Some(order) = order {
+            let order = EfiBootOrder {
+                boot_order: order.to_vec(),
+            };
+            order.store(&store, LoadOptionType::Boot).unwrap();
+        }
+        store
+    }
+
+    fn boot_manager(boot_options: &[EfiLoadOption], order: Option<&[u16]>) -> EfiBootManager {
+        let store = boot_store(boot_options, order);
+        EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap()
+    }
+
+    // The Boot#### part of the boot, from discovery to the started images,
+    // with the entries in BootOrder in index order
+    fn scan(firmware: &ScriptedFirmware, boot_options: &[EfiLoadOption]) -> Vec<String> {
+        let order: Vec<u16> = (0..boot_options.len() as u16).collect();
+        scan_in_order(firmware, boot_options, &order)
+    }
+
+    fn scan_in_order(
+        firmware: &ScriptedFirmware,
+        boot_options: &[EfiLoadOption],
+        order: &[u16],
+    ) -> Vec<String> {
+        let fs_device_paths = discover_nvme_filesystems(firmware).unwrap();
+        let boot_mgr = boot_manager(boot_options, Some(order));
+        let candidates = boot_option_candidates(&boot_mgr, &fs_device_paths, |_| ());
+        boot_candidates(firmware, &candidates);
+        firmware.events.take()
+    }
+
+    fn started(source: &str, path: &DevicePath) -> String {
+        format!("start {} {}", source, device_path_text(path))
+    }
+
+    #[test]
+    fn multiple_nvme_disks() {
+        let firmware = ScriptedFirmware::new(&[
+            (Disk::Sata(1), OTHER_ESP),
+            (Disk::Nvme(2), ESP),
+            (Disk::Nvme(3), ROOT),
+        ]);
+        let events = scan(
+            &firmware,
+            &[
+                on_partition("SATA disk", OTHER_ESP),
+                on_partition("ubuntu", ROOT),
+                on_partition("fedora", ESP),
+            ],
+        );
+        assert_eq!(
+            events,
+            [
+                "connect".to_string(),
+                started("Boot0001", &image(Disk::Nvme(3), ROOT)),
+                started("Boot0002", &image(Disk::Nvme(2), ESP)),
+            ]
+        );
+    }
+
+    #[test]
+    fn boot_order_is_followed() {
+        let firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP), (Disk::Nvme(3), ROOT)]);
+        // Boot0000 isn't in BootOrder, the firmware wouldn't boot it either
+        let events = scan_in_order(
+            &firmware,
+            &[
+                on_partition("stale", ESP),
+                on_partition("ubuntu", ROOT),
+                on_partition("fedora", ESP),
+            ],
+            &[2, 1],
+        );
+        assert_eq!(
+            events,
+            [
+                "connect".to_string(),
+                started("Boot0002", &image(Disk::Nvme(2), ESP)),
+                started("Boot0001", &image(Disk::Nvme(3), ROOT)),
+            ]
+        );
+    }
+
+    #[test]
+    fn inactive_entry_is_skipped() {
+        let firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP), (Disk::Nvme(3), ROOT)]);
+        let mut disabled = on_partition("disabled", ESP);
+        disabled.attributes = LoadOptionAttributes::from(0);
+        let events = scan(&firmware, &[disabled, on_partition("ubuntu", ROOT)]);
+        assert_eq!(
+            events,
+            [
+                "connect".to_string(),
+                started("Boot0001", &image(Disk::Nvme(3), ROOT)),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_match() {
+        let firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP), (Disk::Sata(1), ROOT)]);
+        // a network entry without a HardDrive node, a partition that isn't
+        // on NVMe and one that isn't anywhere
+        let pxe = boot_option("PXE", device_path(Some(Disk::Sata(4)), None, None));
+        assert_eq!(
+            match_nvme_boot_option(&pxe, &discover_nvme_filesystems(&firmware).unwrap())
+                .unwrap_err()
+                .to_string(),
+            "no HardDrive node"
+        );
+        let events = scan(
+            &firmware,
+            &[
+                pxe,
+                on_partition("SATA disk", ROOT),
+                on_partition("old install", OTHER_ESP),
+            ],
+        );
+        assert_eq!(events, ["connect"]);
+    }
+
+    #[test]
+    fn nvme_is_only_seen_after_connecting() {
+        let firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP)]);
+        assert!(firmware.filesystems().unwrap().is_empty());
+        assert_eq!(discover_nvme_filesystems(&firmware).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn cloned_disks() {
+        // both disks carry the same partition GUID
+        let firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP), (Disk::Nvme(3), ESP)]);
+        let fs_device_paths = discover_nvme_filesystems(&firmware).unwrap();
+        let option = on_partition("ubuntu", ESP);
+        let matches = match_nvme_boot_option(&option, &fs_device_paths).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].as_bytes(), image(Disk::Nvme(2), ESP).as_bytes());
+        assert_eq!(matches[1].as_bytes(), image(Disk::Nvme(3), ESP).as_bytes());
+
+        let events = scan(&firmware, &[option]);
+        assert_eq!(
+            events,
+            [
+                "connect".to_string(),
+                started("Boot0000", &image(Disk::Nvme(2), ESP)),
+                started("Boot0000", &image(Disk::Nvme(3), ESP)),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        let mut firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP), (Disk::Nvme(3), ROOT)]);
+        firmware.remove_file(&image(Disk::Nvme(2), ESP));
+        let events = scan(
+            &firmware,
+            &[on_partition("deleted", ESP), on_partition("ubuntu", ROOT)],
+        );
+        assert_eq!(
+            events,
+            [
+                "connect".to_string(),
+                format!("missing {}", device_path_text(&image(Disk::Nvme(2), ESP))),
+                started("Boot0001", &image(Disk::Nvme(3), ROOT)),
+            ]
+        );
+    }
+
+    #[test]
+    fn entry_without_file_path_boots_the_default_loader() {
+        let mut firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP)]);
+        let loader = device_path(Some(Disk::Nvme(2)), Some(ESP), Some(DEFAULT_LOADER));
+        firmware.files.push(loader.to_boxed());
+        let option = boot_option("whole partition", device_path(None, Some(ESP), None));
+        let fs_device_paths = discover_nvme_filesystems(&firmware).unwrap();
+        let matches = match_nvme_boot_option(&option, &fs_device_paths).unwrap();
+        assert_eq!(matches[0].as_bytes(), loader.as_bytes());
+
+        let events = scan(&firmware, &[option]);
+        assert_eq!(
+            events,
+            ["connect".to_string(), started("Boot0000", &loader)]
+        );
+
+        // loading the bare filesystem path reads the same file
+        firmware.events.take();
+        assert_eq!(
+            firmware
+                .load_image(&Candidate {
+                    source: "Boot0000".to_string(),
+                    device_path: fs_device_paths[0].to_boxed(),
+                    selection: (),
+                })
+                .unwrap(),
+            device_path_text(&loader)
+        );
+        assert!(with_default_loader(&image(Disk::Nvme(2), ESP))
+            .unwrap()
+            .file_path()
+            .is_some_and(
+                |f| f.path_name().to_cstring16().unwrap() == CString16::try_from(LOADER).unwrap()
+            ));
+    }
+
+    #[test]
+    fn image_failure_falls_back() {
+        // the original disk's image is rejected, its clone and the next
+        // entry are still tried
+        let mut firmware = ScriptedFirmware::new(&[
+            (Disk::Nvme(2), ESP),
+            (Disk::Nvme(3), ESP),
+            (Disk::Nvme(4), ROOT),
+        ]);
+        firmware.rejected.push(image(Disk::Nvme(2), ESP));
+        let events = scan(
+            &firmware,
+            &[on_partition("ubuntu", ESP), on_partition("fedora", ROOT)],
+        );
+        assert_eq!(
+            events,
+            [
+                "connect".to_string(),
+                format!("rejected {}", device_path_text(&image(Disk::Nvme(2), ESP))),
+                started("Boot0000", &image(Disk::Nvme(3), ESP)),
+                started("Boot0001", &image(Disk::Nvme(4), ROOT)),
+            ]
+        );
+    }
+
+    // What the fast path sees: the filesystems once the cached disk is connected
+    fn cached_current(
+        firmware: &ScriptedFirmware,
+        options: &[EfiLoadOption],
+        order: &[u16],
+        index: usize,
+        cached: &DevicePath,
+    ) -> bool {
+        is_cached_option_current(
+            &boot_manager(options, Some(order)),
+            index,
+            cached,
+            &firmware.filesystems().unwrap(),
+        )
+    }
+
+    #[test]
+    fn cached_option_must_still_be_first() {
+        let firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP), (Disk::Nvme(3), ROOT)]);
+        firmware.connect_drivers().unwrap();
+        let cached = image(Disk::Nvme(2), ESP);
+        let mut inactive = on_partition("disabled", ROOT);
+        inactive.attributes = LoadOptionAttributes::from(0);
+        let options = [
+            boot_option("PXE", device_path(Some(Disk::Sata(4)), None, None)),
+            on_partition("ubuntu", ESP),
+            on_partition("fedora", ROOT),
+            inactive,
+        ];
+        let current =
+            |order: &[u16], index| cached_current(&firmware, &options, order, index, &cached);
+        // network and inactive entries ahead of it don't count
+        assert!(current(&[0, 3, 1, 2], 1));
+        // the operator moved another entry up
+        assert!(!current(&[2, 1], 1));
+        // the entry was dropped from BootOrder
+        assert!(!current(&[0, 2], 1));
+        // the entry now points at another partition
+        assert!(!current(&[2, 1], 2));
+    }
+
+    #[test]
+    fn cached_option_after_a_sata_entry() {
+        let firmware = ScriptedFirmware::new(&[(Disk::Sata(1), OTHER_ESP), (Disk::Nvme(2), ESP)]);
+        firmware.connect_drivers().unwrap();
+        let cached = image(Disk::Nvme(2), ESP);
+        let options = [
+            on_partition("SATA disk", OTHER_ESP),
+            on_partition("ubuntu", ESP),
+            on_partition("old install", ROOT),
+        ];
+        // the scan skips the SATA entry, so does the check
+        assert!(cached_current(&firmware, &options, &[0, 1], 1, &cached));
+        // a partition on no filesystem may be on an NVMe disk that isn't
+        // connected yet
+        assert!(!cached_current(&firmware, &options, &[2, 1], 1, &cached));
+    }
+
+    #[test]
+    fn cached_option_ignores_boot_next() {
+        // the firmware already followed BootNext to start jumpstart, the
+        // scan doesn't look at it
+        let firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP), (Disk::Nvme(3), ROOT)]);
+        firmware.connect_drivers().unwrap();
+        let options = [on_partition("ubuntu", ESP), on_partition("fedora", ROOT)];
+        let store = boot_store(&options, Some(&[0, 1]));
+        set_boot_next(&store, 1).unwrap();
+        let boot_mgr = EfiBootManager::new_from_variables(&store, LoadOptionType::Boot).unwrap();
+        assert!(is_cached_option_current(
+            &boot_mgr,
+            0,
+            &image(Disk::Nvme(2), ESP),
+            &firmware.filesystems().unwrap(),
+        ));
+    }
+
+    #[test]
+    fn cached_option_must_have_the_same_file() {
+        let firmware = ScriptedFirmware::new(&[(Disk::Nvme(2), ESP)]);
+        firmware.connect_drivers().unwrap();
+        let grub = r"\EFI\ubuntu\grubx64.efi";
+        let options = [boot_option(
+            "ubuntu",
+            device_path(None, Some(ESP), Some(grub)),
+        )];
+        let cached = |file| device_path(Some(Disk::Nvme(2)), Some(ESP), Some(file));
+        assert!(!cached_current(
+            &firmware,
+            &options,
+            &[0],
+            0,
+            &cached(LOADER)
+        ));
+        assert!(cached_current(&firmware, &options, &[0], 0, &cached(grub)));
+
+        // an entry without a file path was cached with the default loader
+        let options = [boot_option(
+            "whole partition",
+            device_path(None, Some(ESP), None),
+        )];
+        assert!(cached_current(
+            &firmware,
+            &options,
+            &[0],
+            0,
+            &cached(DEFAULT_LOADER)
+        ));
+        assert!(!cached_current(
+            &firmware,
+            &options,
+            &[0],
+            0,
+            &cached(LOADER)
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+extern crate alloc;
+
+use core::fmt::Display;
+
+use alloc::{vec, vec::Vec};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use uefi::{cstr16, CStr16};
+
+use crate::{
+    jumpstart_vars::{read_jumpstart_var, write_jumpstart_var},
+    var_store::VariableStore,
+};
+
+// A/B slot state. Bootable slots are tried highest priority first, the
+// active slot first if both have the same one. The OS marks a successful
+// boot by writing the active slot's tries_remaining back to a non-zero
+// value, or gives up on a slot by setting its priority to 0.
+//
+// Layout (version 1):
+//   u8 version, u8 active slot (0 = A, 1 = B), u8[2] reserved,
+//   then for slot A and slot B: u8 priority, u8 tries_remaining, u8[2] reserved
+pub const SLOTS_VAR_NAME: &CStr16 = cstr16!("JumpstartSlots");
+const SLOTS_VAR_VERSION: u8 = 1;
+const SLOTS_VAR_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Slot::A => 0,
+            Slot::B => 1,
+        }
+    }
+}
+
+impl Display for Slot {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Slot::A => write!(f, "A"),
+            Slot::B => write!(f, "B"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotInfo {
+    // 0 means the slot is not bootable
+    pub priority: u8,
+    pub tries_remaining: u8,
+}
+
+impl SlotInfo {
+    fn is_bootable(&self) -> bool {
+        self.priority > 0 && self.tries_remaining > 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotState {
+    pub active: Slot,
+    pub slots: [SlotInfo; 2],
+}
+
+impl TryFrom<&[u8]> for SlotState {
+    type Error = anyhow::Error;
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < SLOTS_VAR_SIZE {
+            return Err(anyhow!("JumpstartSlots data too short"));
+        }
+        if data[0] != SLOTS_VAR_VERSION {
+            return Err(anyhow!("unsupported JumpstartSlots version {}", data[0]));
+        }
+        let active = match data[1] {
+            0 => Slot::A,
+            1 => Slot::B,
+            n => return Err(anyhow!("invalid active slot {}", n)),
+        };
+        let slot = |offset: usize| SlotInfo {
+            priority: data[offset],
+            tries_remaining: data[offset + 1],
+        };
+        Ok(SlotState {
+            active,
+            slots: [slot(4), slot(8)],
+        })
+    }
+}
+
+impl From<&SlotState> for Vec<u8> {
+    fn from(state: &SlotState) -> Vec<u8> {
+        let mut v = Vec::with_capacity(SLOTS_VAR_SIZE);
+        v.extend_from_slice(&[SLOTS_VAR_VERSION, state.active.index() as u8, 0, 0]);
+        for slot in state.slots.iter() {
+            v.extend_from_slice(&[slot.priority, slot.tries_remaining, 0, 0]);
+        }
+        v
+    }
+}
+
+impl SlotState {
+    // Fresh state: A preferred, both slots bootable
+    pub fn new(tries: u8) -> Self {
+        SlotState {
+            active: Slot::A,
+            slots: [
+                SlotInfo {
+                    priority: 15,
+                    tries_remaining: tries,
+                },
+                SlotInfo {
+                    priority: 14,
+                    tries_remaining: tries,
+                },
+            ],
+        }
+    }
+
+    pub fn slot(&self, slot: Slot) -> &SlotInfo {
+        &self.slots[slot.index()]
+    }
+
+    fn slot_mut(&mut self, slot: Slot) -> &mut SlotInfo {
+        &mut self.slots[slot.index()]
+    }
+
+    // Read the state variable, creating it if it is missing or unreadable
+    pub fn load(store: &dyn VariableStore, default_tries: u8) -> Result<Self> {
+        let state = match read_jumpstart_var(store, SLOTS_VAR_NAME)? {
+            Some(data) => SlotState::try_from(data.as_ref()).unwrap_or_else(|e| {
+                warn!("Resetting invalid slot state: {:?}", e);
+                SlotState::new(default_tries)
+            }),
+            None => {
+                info!("No slot state found, starting with slot A");
+                SlotState::new(default_tries)
+            }
+        };
+        Ok(state)
+    }
+
+    pub fn store(&self, store: &dyn VariableStore) -> Result<()> {
+        write_jumpstart_var(store, SLOTS_VAR_NAME, &Vec::from(self))
+    }
+
+    // The slot for this boot: the bootable one with the highest priority,
+    // the active one on a tie or if neither is bootable
+    fn selected(&self) -> Slot {
+        let (active, other) = (self.active, self.active.other());
+        let (a, b) = (self.slot(active), self.slot(other));
+        if b.is_bootable() && (!a.is_bootable() || b.priority > a.priority) {
+            other
+        } else {
+            active
+        }
+    }
+
+    // Slots in the order they are tried: the selected one, then the other
+    // one if it is bootable
+    pub fn try_order(&self) -> Vec<Slot> {
+        let first = self.selected();
+        let mut order = vec![first];
+        if self.slot(first.other()).is_bootable() {
+            order.push(first.other());
+        }
+        order
+    }
+
+    // Make `slot` the active slot and consume one of its tries. Called once
+    // the image is loaded, and must be stored before it is started.
+    pub fn consume(&mut self, slot: Slot) {
+        if slot != self.active {
+            info!("Switching from slot {} to slot {}", self.active, slot);
+            self.active = slot;
+        } else if !self.slot(slot).is_bootable() {
+            warn!("No bootable slot left, retrying slot {}", slot);
+        }
+
+        let info = self.slot_mut(slot);
+        info.tries_remaining = info.tries_remaining.saturating_sub(1);
+        info!(
+            "Booting slot {} (priority {}, {} tries left)",
+            slot, info.priority, info.tries_remaining
+        );
+    }
+
+    // The slot's image couldn't even be started, don't waste more tries on it
+    pub fn mark_failed(&mut self, slot: Slot) {
+        self.slot_mut(slot).tries_remaining = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::var_store::MemoryStore;
+
+    fn state(active: Slot, a: (u8, u8), b: (u8, u8)) -> SlotState {
+        let info = |(priority, tries_remaining)| SlotInfo {
+            priority,
+            tries_remaining,
+        };
+        SlotState {
+            active,
+            slots: [info(a), info(b)],
+        }
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let original = state(Slot::B, (15, 0), (14, 2));
+        let data = Vec::from(&original);
+        assert_eq!(data, [1, 1, 0, 0, 15, 0, 0, 0, 14, 2, 0, 0]);
+        assert_eq!(SlotState::try_from(data.as_slice()).unwrap(), original);
+
+        assert!(SlotState::try_from(&data[..8]).is_err());
+        let mut version = data.clone();
+        version[0] = 2;
+        assert!(SlotState::try_from(version.as_slice()).is_err());
+        let mut active = data.clone();
+        active[1] = 2;
+        assert!(SlotState::try_from(active.as_slice()).is_err());
+    }
+
+    #[test]
+    fn load_starts_fresh_without_a_valid_state() {
+        let store = MemoryStore::new();
+        assert_eq!(SlotState::load(&store, 3).unwrap(), SlotState::new(3));
+
+        write_jumpstart_var(&store, SLOTS_VAR_NAME, &[1, 2, 3]).unwrap();
+        assert_eq!(SlotState::load(&store, 3).unwrap(), SlotState::new(3));
+
+        let stored = state(Slot::B, (0, 3), (14, 1));
+        stored.store(&store).unwrap();
+        assert_eq!(SlotState::load(&store, 3).unwrap(), stored);
+    }
+
+    #[test]
+    fn try_order() {
+        assert_eq!(SlotState::new(3).try_order(), [Slot::A, Slot::B]);
+        // out of tries or given up on by the OS
+        assert_eq!(state(Slot::A, (15, 0), (14, 3)).try_order(), [Slot::B]);
+        assert_eq!(state(Slot::A, (0, 3), (14, 3)).try_order(), [Slot::B]);
+        assert_eq!(
+            state(Slot::B, (15, 3), (14, 1)).try_order(),
+            [Slot::A, Slot::B]
+        );
+        assert_eq!(
+            state(Slot::B, (15, 3), (15, 1)).try_order(),
+            [Slot::B, Slot::A]
+        );
+        // nothing bootable, the active slot is still tried
+        assert_eq!(state(Slot::B, (15, 0), (0, 0)).try_order(), [Slot::B]);
+    }
+
+    #[test]
+    fn tries_run_out_and_switch_slots() {
+        let mut state = SlotState::new(2);
+        for tries_left in [1, 0] {
+            assert_eq!(state.try_order()[0], Slot::A);
+            state.consume(Slot::A);
+            assert_eq!(state.slot(Slot::A).tries_remaining, tries_left);
+        }
+        assert_eq!(state.try_order(), [Slot::B]);
+        state.consume(Slot::B);
+        assert_eq!(state.active, Slot::B);
+        assert_eq!(state.slot(Slot::B).tries_remaining, 1);
+
+        // a slot that doesn't start loses all its tries
+        state.mark_failed(Slot::B);
+        assert_eq!(state.try_order(), [Slot::B]);
+        state.consume(Slot::B);
+        assert_eq!(state.slot(Slot::B).tries_remaining, 0);
+    }
+}
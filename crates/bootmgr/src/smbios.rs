@@ -0,0 +1,282 @@
+extern crate alloc;
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, Result};
+use log::info;
+use uefi::Guid;
+
+// SMBIOS structure table, DMTF DSP0134. Finding the table is up to the
+// caller, e.g. through the UEFI configuration table
+const END_OF_TABLE: u8 = 127;
+
+pub const TYPE_BIOS: u8 = 0;
+pub const TYPE_SYSTEM: u8 = 1;
+pub const TYPE_OEM_STRINGS: u8 = 11;
+
+// One structure: formatted area and its strings
+pub struct Structure<'a> {
+    pub kind: u8,
+    pub handle: u16,
+    pub data: &'a [u8],
+    strings: Vec<&'a [u8]>,
+}
+
+impl<'a> Structure<'a> {
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    // String number `n`, counting from 1 as SMBIOS does
+    pub fn string_by_number(&self, n: u8) -> Option<String> {
+        let s = self.strings.get((n as usize).checked_sub(1)?)?;
+        Some(String::from_utf8_lossy(s).trim().to_string())
+    }
+
+    // String referenced by the byte at `offset`
+    pub fn string(&self, offset: usize) -> Option<String> {
+        self.string_by_number(self.byte(offset)?)
+    }
+}
+
+// All structures up to the end-of-table marker. A truncated structure
+// ends the walk
+pub fn structures(table: &[u8]) -> Vec<Structure<'_>> {
+    let mut structures = Vec::new();
+    let mut rest = table;
+    while rest.len() >= 4 {
+        let (kind, length) = (rest[0], rest[1] as usize);
+        if length < 4 || rest.len() < length {
+            break;
+        }
+        let data = &rest[..length];
+        // strings follow the formatted area and end with a double null
+        let Some(end) = rest[length..].windows(2).position(|w| w == [0, 0]) else {
+            break;
+        };
+        let string_area = &rest[length..length + end];
+        let strings = if string_area.is_empty() {
+            Vec::new()
+        } else {
+            string_area.split(|b| *b == 0).collect()
+        };
+        structures.push(Structure {
+            kind,
+            handle: u16::from_le_bytes([rest[2], rest[3]]),
+            data,
+            strings,
+        });
+        if kind == END_OF_TABLE {
+            break;
+        }
+        rest = &rest[length + end + 2..];
+    }
+    structures
+}
+
+// BIOS (type 0) and system (type 1) identification, plus the OEM strings
+// (type 11) provisioning tools use to pass settings
+#[derive(Debug, Clone, Default)]
+pub struct SystemInfo {
+    pub bios_vendor: Option<String>,
+    pub bios_version: Option<String>,
+    pub bios_date: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub version: Option<String>,
+    // Dell's service tag
+    pub serial: Option<String>,
+    pub uuid: Option<Guid>,
+    pub sku: Option<String>,
+    pub family: Option<String>,
+    pub oem_strings: Vec<String>,
+}
+
+impl SystemInfo {
+    pub fn read(structures: &[Structure]) -> Self {
+        let mut info = SystemInfo::default();
+        for s in structures {
+            match s.kind {
+                TYPE_BIOS => {
+                    info.bios_vendor = s.string(0x04);
+                    info.bios_version = s.string(0x05);
+                    info.bios_date = s.string(0x08);
+                }
+                TYPE_SYSTEM => {
+                    info.manufacturer = s.string(0x04);
+                    info.product = s.string(0x05);
+                    info.version = s.string(0x06);
+                    info.serial = s.string(0x07);
+                    // same mixed endian layout as EFI GUIDs since SMBIOS 2.6
+                    info.uuid = s
+                        .data
+                        .get(0x08..0x18)
+                        .map(|b| Guid::from_bytes(b.try_into().unwrap()));
+                    info.sku = s.string(0x19);
+                    info.family = s.string(0x1a);
+                }
+                TYPE_OEM_STRINGS => {
+                    let count = s.byte(0x04).unwrap_or(0);
+                    info.oem_strings
+                        .extend((1..=count).filter_map(|n| s.string_by_number(n)));
+                }
+                _ => {}
+            }
+        }
+        info
+    }
+}
+
+impl SystemInfo {
+    pub fn log(&self) {
+        let field = |value: &Option<String>| value.clone().unwrap_or_else(|| "?".to_string());
+        info!(
+            "System: {} {} (SKU {}), service tag {}",
+            field(&self.manufacturer),
+            field(&self.product),
+            field(&self.sku),
+            field(&self.serial)
+        );
+        info!(
+            "BIOS: {} {} ({})",
+            field(&self.bios_vendor),
+            field(&self.bios_version),
+            field(&self.bios_date)
+        );
+    }
+
+    // Field by the name used in config model sections
+    pub fn field(&self, name: &str) -> Result<Option<&str>> {
+        let value = match name {
+            "manufacturer" => &self.manufacturer,
+            "product" => &self.product,
+            "version" => &self.version,
+            "sku" => &self.sku,
+            "family" => &self.family,
+            "bios_version" => &self.bios_version,
+            "service_tag" => &self.serial,
+            _ => return Err(anyhow!("unknown system field '{}'", name)),
+        };
+        Ok(value.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A structure: type, length, handle, the rest of the formatted area and
+    // its strings
+    fn structure(kind: u8, handle: u16, formatted: &[u8], strings: &[&str]) -> Vec<u8> {
+        let mut data = vec![kind, (4 + formatted.len()) as u8];
+        data.extend_from_slice(&handle.to_le_bytes());
+        data.extend_from_slice(formatted);
+        for s in strings {
+            data.extend_from_slice(s.as_bytes());
+            data.push(0);
+        }
+        if strings.is_empty() {
+            data.push(0);
+        }
+        data.push(0);
+        data
+    }
+
+    fn system(product: &str) -> Vec<u8> {
+        // manufacturer, product, version, serial, UUID, wake-up type, SKU, family
+        let mut formatted = vec![1, 2, 0, 3];
+        formatted.extend_from_slice(&[0x11; 16]);
+        formatted.extend_from_slice(&[6, 4, 0]);
+        structure(
+            TYPE_SYSTEM,
+            1,
+            &formatted,
+            &["Dell Inc.", product, "  7XYZ123 "],
+        )
+    }
+
+    fn end_of_table() -> Vec<u8> {
+        structure(END_OF_TABLE, 0xffff, &[], &[])
+    }
+
+    #[test]
+    fn system_info() {
+        let table = [
+            structure(
+                TYPE_BIOS,
+                0,
+                &[1, 2, 0, 0, 3],
+                &["Dell Inc.", "1.2.3", "01/02/2024"],
+            ),
+            system("PowerEdge R650"),
+            structure(TYPE_OEM_STRINGS, 2, &[2], &["jumpstart.timeout=5", "other"]),
+            end_of_table(),
+        ]
+        .concat();
+        let structures = structures(&table);
+        assert_eq!(structures.len(), 4);
+        let info = SystemInfo::read(&structures);
+        assert_eq!(info.bios_vendor.as_deref(), Some("Dell Inc."));
+        assert_eq!(info.bios_version.as_deref(), Some("1.2.3"));
+        assert_eq!(info.bios_date.as_deref(), Some("01/02/2024"));
+        assert_eq!(info.product.as_deref(), Some("PowerEdge R650"));
+        // string number 0 means no string
+        assert_eq!(info.version, None);
+        assert_eq!(info.serial.as_deref(), Some("7XYZ123"));
+        assert_eq!(info.uuid, Some(Guid::from_bytes([0x11; 16])));
+        assert_eq!(info.sku, None);
+        assert_eq!(info.oem_strings, ["jumpstart.timeout=5", "other"]);
+        assert_eq!(info.field("service_tag").unwrap(), Some("7XYZ123"));
+        assert!(info.field("serial").is_err());
+    }
+
+    #[test]
+    fn empty_string_sets() {
+        let table = [
+            structure(TYPE_SYSTEM, 1, &[1, 2, 0, 3], &[]),
+            // claims more OEM strings than it has
+            structure(TYPE_OEM_STRINGS, 2, &[3], &[]),
+            end_of_table(),
+        ]
+        .concat();
+        let structures = structures(&table);
+        assert_eq!(structures.len(), 3);
+        assert_eq!(structures[0].string(0x04), None);
+        assert_eq!(structures[0].string_by_number(0), None);
+        let info = SystemInfo::read(&structures);
+        assert_eq!(info.manufacturer, None);
+        assert_eq!(info.uuid, None);
+        assert!(info.oem_strings.is_empty());
+    }
+
+    #[test]
+    fn truncated_structures_end_the_walk() {
+        let good = system("PowerEdge R650");
+
+        // formatted area longer than the table
+        let mut table = good.clone();
+        table.extend_from_slice(&[TYPE_OEM_STRINGS, 0x20, 2, 0, 1]);
+        assert_eq!(structures(&table).len(), 1);
+
+        // strings without the double null
+        let mut table = good.clone();
+        table.extend_from_slice(&structure(TYPE_OEM_STRINGS, 2, &[1], &["abc"]));
+        table.truncate(table.len() - 1);
+        assert_eq!(structures(&table).len(), 1);
+
+        // a length below the header size
+        let mut table = good.clone();
+        table.extend_from_slice(&[TYPE_OEM_STRINGS, 2, 2, 0, 0, 0]);
+        assert_eq!(structures(&table).len(), 1);
+
+        // a short header, and nothing at all
+        assert_eq!(structures(&good[..3]).len(), 0);
+        assert_eq!(structures(&[]).len(), 0);
+
+        // nothing after the end-of-table marker is read
+        let table = [end_of_table(), good].concat();
+        assert_eq!(structures(&table).len(), 1);
+    }
+}
@@ -0,0 +1,142 @@
+extern crate alloc;
+
+use core::cell::RefCell;
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, Result};
+use uefi::{
+    table::runtime::{RuntimeServices, VariableAttributes, VariableVendor},
+    CStr16, CString16, Status,
+};
+
+// Where variables live: the firmware, efivarfs on Linux, or memory for tests.
+// All variable access in this crate goes through it
+pub trait VariableStore {
+    // Data and attributes, None if the variable doesn't exist
+    fn get(
+        &self,
+        name: &CStr16,
+        vendor: &VariableVendor,
+    ) -> Result<Option<(Vec<u8>, VariableAttributes)>>;
+
+    fn set(
+        &self,
+        name: &CStr16,
+        vendor: &VariableVendor,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<()>;
+
+    fn delete(&self, name: &CStr16, vendor: &VariableVendor) -> Result<()>;
+
+    // Names and vendors of all variables
+    fn keys(&self) -> Result<Vec<(CString16, VariableVendor)>>;
+}
+
+impl VariableStore for RuntimeServices {
+    fn get(
+        &self,
+        name: &CStr16,
+        vendor: &VariableVendor,
+    ) -> Result<Option<(Vec<u8>, VariableAttributes)>> {
+        match self.get_variable_boxed(name, vendor) {
+            Ok((data, attributes)) => Ok(Some((data.into_vec(), attributes))),
+            Err(e) if e.status() == Status::NOT_FOUND => Ok(None),
+            Err(e) => Err(anyhow::Error::msg(e)),
+        }
+    }
+
+    fn set(
+        &self,
+        name: &CStr16,
+        vendor: &VariableVendor,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<()> {
+        self.set_variable(name, vendor, attributes, data)
+            .map_err(anyhow::Error::msg)
+    }
+
+    fn delete(&self, name: &CStr16, vendor: &VariableVendor) -> Result<()> {
+        self.delete_variable(name, vendor)
+            .map_err(anyhow::Error::msg)
+    }
+
+    fn keys(&self) -> Result<Vec<(CString16, VariableVendor)>> {
+        let mut keys = Vec::new();
+        for key in self.variable_keys().map_err(anyhow::Error::msg)? {
+            let name = key.name().map_err(anyhow::Error::msg)?;
+            keys.push((CString16::from(name), key.vendor));
+        }
+        Ok(keys)
+    }
+}
+
+type MemoryVariables = BTreeMap<(String, VariableVendor), (VariableAttributes, Vec<u8>)>;
+
+// Variables in memory, e.g. a recorded NVRAM dump replayed in tests
+#[derive(Default)]
+pub struct MemoryStore {
+    vars: RefCell<MemoryVariables>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl VariableStore for MemoryStore {
+    fn get(
+        &self,
+        name: &CStr16,
+        vendor: &VariableVendor,
+    ) -> Result<Option<(Vec<u8>, VariableAttributes)>> {
+        Ok(self
+            .vars
+            .borrow()
+            .get(&(name.to_string(), *vendor))
+            .map(|(attributes, data)| (data.clone(), *attributes)))
+    }
+
+    // Like SetVariable, empty data deletes the variable
+    fn set(
+        &self,
+        name: &CStr16,
+        vendor: &VariableVendor,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<()> {
+        if data.is_empty() {
+            return self.delete(name, vendor);
+        }
+        self.vars
+            .borrow_mut()
+            .insert((name.to_string(), *vendor), (attributes, data.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&self, name: &CStr16, vendor: &VariableVendor) -> Result<()> {
+        self.vars
+            .borrow_mut()
+            .remove(&(name.to_string(), *vendor))
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("{} not found", name))
+    }
+
+    fn keys(&self) -> Result<Vec<(CString16, VariableVendor)>> {
+        self.vars
+            .borrow()
+            .keys()
+            .map(|(name, vendor)| {
+                CString16::try_from(name.as_str())
+                    .map(|name| (name, *vendor))
+                    .map_err(anyhow::Error::msg)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,282 @@
+// jsctl: jumpstart's companion on Linux. Manages Boot#### entries and
+// jumpstart's own variables through efivarfs
+use std::{
+    env, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{anyhow, Context, Result};
+use bootmgr::{
+    boot_vars::{
+        device_path_text, EfiBootManager, EfiLoadOption, LoadOptionAttributes,
+        LoadOptionAttributesBits, LoadOptionType,
+    },
+    efivarfs::{Efivarfs, EFIVARS_DIR},
+    jumpstart_vars::{
+        decode_config_var, decode_load_options, delete_jumpstart_var, encode_config_var,
+        encode_load_options, write_jumpstart_var, CONFIG_ONCE_VAR_NAME, CONFIG_VAR_NAME,
+        JUMPSTART_VENDOR,
+    },
+    var_store::VariableStore,
+};
+use uefi::{
+    cstr16,
+    proto::device_path::{
+        build::{self, DevicePathBuilder},
+        media::{PartitionFormat, PartitionSignature},
+    },
+    table::runtime::VariableVendor,
+    CStr16, CString16, Guid,
+};
+
+const USAGE: &str = "\
+usage: jsctl [--efivars DIR] COMMAND
+
+commands:
+  list                      show BootOrder and the Boot#### entries
+  create --partition DEV --loader PATH --description TEXT [--options TEXT] [--first]
+                            add a Boot#### entry starting PATH on partition DEV,
+                            with jumpstart load options TEXT
+  delete XXXX               delete BootXXXX and drop it from BootOrder
+  set-config FILE           store FILE as JumpstartConfig, - reads stdin
+  set-once FILE             store FILE as JumpstartConfigOnce for the next boot
+  clear-config              delete JumpstartConfig
+  status                    show jumpstart's variables
+";
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn read_u16(vars: &Efivarfs, name: &CStr16) -> Result<Option<u16>> {
+    Ok(vars
+        .get(name, &VariableVendor::GLOBAL_VARIABLE)?
+        .and_then(|(data, _)| Some(u16::from_le_bytes(data.get(..2)?.try_into().unwrap()))))
+}
+
+fn list(vars: &Efivarfs) -> Result<()> {
+    if let Some(current) = read_u16(vars, cstr16!("BootCurrent"))? {
+        println!("BootCurrent: {:04X}", current);
+    }
+    if let Some(next) = read_u16(vars, cstr16!("BootNext"))? {
+        println!("BootNext: {:04X}", next);
+    }
+    let manager = EfiBootManager::new_from_variables(vars, LoadOptionType::Boot)?;
+    let order: Vec<String> = manager
+        .boot_order
+        .boot_order
+        .iter()
+        .map(|i| format!("{:04X}", i))
+        .collect();
+    println!("BootOrder: {}", order.join(","));
+    for (index, option) in manager.boot_options.iter() {
+        let active = if option.is_active() { "*" } else { " " };
+        println!("Boot{:04X}{} {}", index, active, option.description);
+        for device_path in option.device_path_list.iter() {
+            println!("      {}", device_path_text(device_path));
+        }
+        let Some(data) = option.optional_data.as_deref() else {
+            continue;
+        };
+        match decode_load_options(data) {
+            Ok(Some(text)) => println!("      jumpstart options: {}", text),
+            Ok(None) => match option.optional_data_as_cstring16() {
+                Some(text) => println!("      options: {}", text),
+                None => println!("      optional data: {}", hex(data)),
+            },
+            Err(e) => println!("      invalid jumpstart options: {}", e),
+        }
+    }
+    Ok(())
+}
+
+fn read_sys(path: &Path) -> Result<u64> {
+    fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .trim()
+        .parse()
+        .with_context(|| format!("unexpected contents of {}", path.display()))
+}
+
+// HardDrive device path node for a GPT partition, from sysfs and udev's
+// by-partuuid links
+fn hard_drive_node(partition: &Path) -> Result<build::media::HardDrive> {
+    let device = fs::canonicalize(partition)
+        .with_context(|| format!("no such device {}", partition.display()))?;
+    let name = device.file_name().context("partition has no device name")?;
+    let sys = fs::canonicalize(Path::new("/sys/class/block").join(name))
+        .with_context(|| format!("{} is not a block device", device.display()))?;
+    let number = read_sys(&sys.join("partition"))
+        .with_context(|| format!("{} is not a partition", device.display()))?;
+    // sysfs counts 512 byte sectors, HardDrive nodes count logical blocks
+    let block_size = read_sys(
+        &sys.parent()
+            .context("partition without a disk")?
+            .join("queue/logical_block_size"),
+    )?;
+    let start = read_sys(&sys.join("start"))? * 512 / block_size;
+    let size = read_sys(&sys.join("size"))? * 512 / block_size;
+
+    let mut partuuid = None;
+    for entry in fs::read_dir("/dev/disk/by-partuuid")? {
+        let entry = entry?;
+        if fs::canonicalize(entry.path()).ok().as_ref() == Some(&device) {
+            partuuid = entry.file_name().to_str().map(str::to_string);
+            break;
+        }
+    }
+    let partuuid = partuuid.with_context(|| format!("{} has no PARTUUID", device.display()))?;
+    let guid = Guid::try_parse(&partuuid)
+        .map_err(|_| anyhow!("{} is not a GPT partition", device.display()))?;
+    Ok(build::media::HardDrive {
+        partition_number: number as u32,
+        partition_start: start,
+        partition_size: size,
+        partition_signature: PartitionSignature::Guid(guid),
+        partition_format: PartitionFormat::GPT,
+    })
+}
+
+struct CreateArgs {
+    partition: PathBuf,
+    loader: String,
+    description: String,
+    options: Option<String>,
+    first: bool,
+}
+
+fn create(vars: &Efivarfs, args: CreateArgs) -> Result<()> {
+    let hard_drive = hard_drive_node(&args.partition)?;
+    let loader = CString16::try_from(args.loader.replace('/', "\\").as_str())
+        .map_err(|_| anyhow!("invalid loader path '{}'", args.loader))?;
+    let mut buffer = Vec::new();
+    let device_path = DevicePathBuilder::with_vec(&mut buffer)
+        .push(&hard_drive)
+        .and_then(|b| b.push(&build::media::FilePath { path_name: &loader }))
+        .and_then(|b| b.finalize())
+        .map_err(|e| anyhow!("failed to build device path: {:?}", e))?
+        .to_boxed();
+    let option = EfiLoadOption {
+        attributes: LoadOptionAttributes::from(LoadOptionAttributesBits::LoadOptionActive as u32),
+        description: CString16::try_from(args.description.as_str())
+            .map_err(|_| anyhow!("invalid description '{}'", args.description))?,
+        device_path_list: vec![device_path],
+        optional_data: args.options.as_deref().map(encode_load_options),
+    };
+
+    let mut manager = EfiBootManager::new_from_variables(vars, LoadOptionType::Boot)?;
+    let index = manager.get_next_available_boot_index()?;
+    manager.set_option(vars, index, option)?;
+    manager.add_to_order(vars, index, args.first)?;
+    println!("Created Boot{:04X}", index);
+    Ok(())
+}
+
+fn delete(vars: &Efivarfs, index: &str) -> Result<()> {
+    let index = u16::from_str_radix(index, 16).map_err(|_| anyhow!("invalid index '{}'", index))?;
+    let mut manager = EfiBootManager::new_from_variables(vars, LoadOptionType::Boot)?;
+    manager.delete_option(vars, index as usize)?;
+    println!("Deleted Boot{:04X}", index);
+    Ok(())
+}
+
+fn read_input(file: &str) -> Result<String> {
+    if file == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        return Ok(text);
+    }
+    fs::read_to_string(file).with_context(|| format!("failed to read {}", file))
+}
+
+// Store config file text in a config variable. jumpstart checks the
+// contents itself when it applies them
+fn set_config(vars: &Efivarfs, name: &CStr16, file: &str) -> Result<()> {
+    let text = read_input(file)?;
+    write_jumpstart_var(vars, name, &encode_config_var(&text))?;
+    println!("Stored {}", name);
+    Ok(())
+}
+
+fn status(vars: &Efivarfs) -> Result<()> {
+    for (name, vendor) in vars.keys()? {
+        if vendor != JUMPSTART_VENDOR {
+            continue;
+        }
+        let Some((data, attributes)) = vars.get(&name, &vendor)? else {
+            continue;
+        };
+        println!("{} ({:?}, {} bytes)", name, attributes, data.len());
+        if name.to_string().starts_with("JumpstartConfig") {
+            match decode_config_var(&data) {
+                Ok(text) => text.lines().for_each(|l| println!("    {}", l)),
+                Err(e) => println!("    invalid: {}", e),
+            }
+        } else {
+            println!("    {}", hex(&data));
+        }
+    }
+    Ok(())
+}
+
+fn parse_create(args: &[String]) -> Result<CreateArgs> {
+    let mut partition = None;
+    let mut loader = None;
+    let mut description = None;
+    let mut options = None;
+    let mut first = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .with_context(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--partition" => partition = Some(PathBuf::from(value()?)),
+            "--loader" => loader = Some(value()?),
+            "--description" => description = Some(value()?),
+            "--options" => options = Some(value()?),
+            "--first" => first = true,
+            _ => return Err(anyhow!("unknown argument '{}'", arg)),
+        }
+    }
+    Ok(CreateArgs {
+        partition: partition.context("--partition is required")?,
+        loader: loader.context("--loader is required")?,
+        description: description.context("--description is required")?,
+        options,
+        first,
+    })
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let (dir, args) = match args {
+        [flag, dir, rest @ ..] if flag == "--efivars" => (dir.as_str(), rest),
+        _ => (EFIVARS_DIR, args),
+    };
+    let vars = Efivarfs::new(dir);
+    match args {
+        [command] if command == "list" => list(&vars),
+        [command, rest @ ..] if command == "create" => create(&vars, parse_create(rest)?),
+        [command, index] if command == "delete" => delete(&vars, index),
+        [command, file] if command == "set-config" => set_config(&vars, CONFIG_VAR_NAME, file),
+        [command, file] if command == "set-once" => set_config(&vars, CONFIG_ONCE_VAR_NAME, file),
+        [command] if command == "clear-config" => delete_jumpstart_var(&vars, CONFIG_VAR_NAME),
+        [command] if command == "status" => status(&vars),
+        _ => Err(anyhow!("{}", USAGE)),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("jsctl: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,119 @@
+extern crate alloc;
+
+use alloc::{format, string::ToString, vec, vec::Vec};
+use anyhow::{anyhow, Context, Result};
+use bootmgr::bls::BlsEntry;
+use log::{info, warn};
+use uefi::{
+    cstr16,
+    data_types::Align,
+    fs::{FileSystem, Path},
+    proto::media::{
+        file::{File, FileAttribute, FileInfo, FileMode},
+        fs::SimpleFileSystem,
+    },
+    table::boot::BootServices,
+    CStr16, CString16, Handle,
+};
+
+use crate::{open_protocol_shared, tpm};
+
+// Boot Loader Specification entries on disk, see bootmgr::bls for the format
+pub const BLS_ENTRIES_DIR: &CStr16 = cstr16!(r"\loader\entries");
+
+// Read all entries from a filesystem, skipping ones that fail to parse.
+// They are unsorted, entries from all partitions are sorted together
+pub fn read_entries(bs: &BootServices, handle: Handle) -> Result<Vec<BlsEntry>> {
+    let mut fs = FileSystem::new(open_protocol_shared::<SimpleFileSystem>(bs, handle)?);
+    let dir = Path::new(BLS_ENTRIES_DIR);
+    if !fs.try_exists(dir).map_err(anyhow::Error::msg)? {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for info in fs.read_dir(dir).map_err(anyhow::Error::msg)? {
+        let Ok(info) = info else {
+            continue;
+        };
+        let file_name = info.file_name().to_string();
+        if info.is_directory() || !file_name.ends_with(".conf") {
+            continue;
+        }
+        let path = CString16::try_from(format!(r"{}\{}", BLS_ENTRIES_DIR, file_name).as_str())
+            .map_err(anyhow::Error::msg)?;
+        let text = fs
+            .read_to_string(Path::new(&path))
+            .map_err(anyhow::Error::msg)?;
+        match BlsEntry::parse(&file_name, &text) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping BLS entry {}: {:?}", file_name, e),
+        }
+    }
+    Ok(entries)
+}
+
+// Rename a file in place through SetInfo. uefi::fs::FileSystem::rename
+// copies and deletes, which is neither atomic nor kind to the ESP.
+fn rename_file(
+    bs: &BootServices,
+    handle: Handle,
+    dir: &CStr16,
+    old_name: &str,
+    new_name: &str,
+) -> Result<()> {
+    let mut sfs = open_protocol_shared::<SimpleFileSystem>(bs, handle)?;
+    let mut root = sfs.open_volume().map_err(anyhow::Error::msg)?;
+    let path = CString16::try_from(format!(r"{}\{}", dir, old_name).as_str())
+        .map_err(anyhow::Error::msg)?;
+    let new_name = CString16::try_from(new_name).map_err(anyhow::Error::msg)?;
+
+    let mut file = root
+        .open(&path, FileMode::ReadWrite, FileAttribute::empty())
+        .map_err(anyhow::Error::msg)?;
+    let info = file
+        .get_boxed_info::<FileInfo>()
+        .map_err(anyhow::Error::msg)?;
+
+    let mut buffer = vec![0u8; 128 + new_name.num_bytes()];
+    let new_info = FileInfo::new(
+        FileInfo::align_buf(&mut buffer).context("failed to align FileInfo buffer")?,
+        info.file_size(),
+        info.physical_size(),
+        *info.create_time(),
+        *info.last_access_time(),
+        *info.modification_time(),
+        info.attribute(),
+        &new_name,
+    )
+    .map_err(|e| anyhow!("failed to create FileInfo: {:?}", e))?;
+    file.set_info(new_info).map_err(anyhow::Error::msg)?;
+    file.flush().map_err(anyhow::Error::msg)?;
+    Ok(())
+}
+
+// Record one more boot attempt for the entry before it is started
+pub fn count_boot_attempt(bs: &BootServices, handle: Handle, entry: &BlsEntry) -> Result<()> {
+    let Some(new_name) = entry.decremented_file_name() else {
+        return Ok(());
+    };
+    info!("Boot counting: {} -> {}", entry.file_name, new_name);
+    rename_file(bs, handle, BLS_ENTRIES_DIR, &entry.file_name, &new_name)
+}
+
+// The kernel's EFI stub loads the initrds itself, measure them before it runs
+pub fn measure_initrds(bs: &BootServices, handle: Handle, entry: &BlsEntry) -> Result<()> {
+    if entry.efi.is_some() || entry.initrd.is_empty() || !tpm::is_present(bs) {
+        return Ok(());
+    }
+    let mut fs = FileSystem::new(open_protocol_shared::<SimpleFileSystem>(bs, handle)?);
+    for initrd in entry.initrd.iter() {
+        let path =
+            CString16::try_from(initrd.replace('/', "\\").as_str()).map_err(anyhow::Error::msg)?;
+        let data = fs
+            .read(Path::new(&path))
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("failed to read initrd {}", initrd))?;
+        tpm::measure(bs, tpm::PCR_FILES, &data, &format!("initrd {}", initrd))?;
+    }
+    Ok(())
+}
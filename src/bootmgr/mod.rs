@@ -1 +0,0 @@
-pub mod boot_vars;
@@ -0,0 +1,280 @@
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, Result};
+use bootmgr::{
+    config::{Config, ConfigLayers},
+    jumpstart_vars::{
+        decode_config_var, decode_load_options, delete_jumpstart_var, read_jumpstart_var,
+        write_jumpstart_var, CONFIG_GOOD_VAR_NAME, CONFIG_ONCE_VAR_NAME, CONFIG_VAR_NAME,
+    },
+    smbios::SystemInfo,
+};
+use log::{info, warn};
+use uefi::{
+    fs::{FileSystem, Path},
+    proto::loaded_image::LoadedImage,
+    table::{boot::BootServices, runtime::RuntimeServices},
+    CStr16,
+};
+
+use crate::{jumpstart_path, tpm, user_path};
+
+// Reading the config layers from the ESP, variables, SMBIOS and load
+// options. Parsing and merging them is in bootmgr::config
+
+// Config file lives next to the drivers directory, relative to the jumpstart directory
+pub const CONFIG_FILE_NAME: &str = "jumpstart.cfg";
+
+// SMBIOS OEM strings carrying config lines, e.g. from QEMU
+// `-smbios type=11,value=jumpstart.timeout=5`
+const OEM_STRING_PREFIX: &str = "jumpstart.";
+
+// What loading the config leaves to do once the merged config is known.
+// A dry run, which can be asked for by any layer, measures nothing and
+// changes no variable
+#[derive(Default)]
+struct Pending {
+    // config text and its event description, for PCR_CONFIG
+    measurements: Vec<(Vec<u8>, String)>,
+    // new last known good copy of JumpstartConfig
+    good_copy: Option<Box<[u8]>>,
+    // JumpstartConfigOnce was read and must go
+    delete_once: bool,
+}
+
+impl Pending {
+    fn measure(&mut self, data: &[u8], description: String) {
+        self.measurements.push((data.to_vec(), description));
+    }
+
+    fn apply(self, bs: &BootServices, rs: &RuntimeServices) -> Result<()> {
+        // first, so a config that hangs the boot is gone after the next reset
+        if self.delete_once {
+            if let Err(e) = delete_jumpstart_var(rs, CONFIG_ONCE_VAR_NAME) {
+                warn!("Failed to delete {}: {:?}", CONFIG_ONCE_VAR_NAME, e);
+            }
+        }
+        for (data, description) in self.measurements.iter() {
+            tpm::measure(bs, tpm::PCR_CONFIG, data, description)?;
+        }
+        if let Some(data) = self.good_copy {
+            if let Err(e) = write_jumpstart_var(rs, CONFIG_GOOD_VAR_NAME, &data) {
+                warn!("Failed to store {}: {:?}", CONFIG_GOOD_VAR_NAME, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Read the config file (if any) and overlay the other layers. A layer that
+// doesn't parse only costs its settings, a typo must not stop the boot.
+// Only a config file named in the load options that is missing is an error
+pub fn load(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    system: Option<&SystemInfo>,
+) -> Result<Config> {
+    let mut pending = Pending::default();
+    // load options go first, they may point at another config file
+    let load_options = match read_load_options(bs)? {
+        Some(options) => {
+            info!("Load options: '{}'", options);
+            pending.measure(options.as_bytes(), format!("load options {}", options));
+            match Config::parse_load_options(&options) {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    warn!("Ignoring invalid load options: {:?}", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let config_file = load_options.as_ref().and_then(|o| o.config_file.as_deref());
+    let file = match read_config_file(bs, config_file, &mut pending)? {
+        Some(text) => match Config::parse_file(&text, system) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                warn!("Ignoring invalid config file: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let layers = ConfigLayers {
+        file,
+        oem_strings: read_oem_strings(system, &mut pending),
+        var: read_config_var(rs, system, &mut pending),
+        once: read_config_once_var(rs, system, &mut pending),
+        load_options,
+    };
+    let config = layers.merge();
+    if config.dry_run {
+        info!("Dry run: config not measured, config variables left as they are");
+    } else {
+        pending.apply(bs, rs)?;
+    }
+    Ok(config)
+}
+
+// Decode and parse a config variable, its text is measured
+fn parse_config_var(
+    name: &CStr16,
+    data: &[u8],
+    system: Option<&SystemInfo>,
+    pending: &mut Pending,
+) -> Result<Config> {
+    let text = decode_config_var(data)?;
+    pending.measure(text.as_bytes(), name.to_string());
+    Config::parse_file(&text, system)
+}
+
+// JumpstartConfig, or the last good copy of it if it is broken. Never fails
+// the boot: a bad variable only costs its settings
+fn read_config_var(
+    rs: &RuntimeServices,
+    system: Option<&SystemInfo>,
+    pending: &mut Pending,
+) -> Option<Config> {
+    let data = match read_jumpstart_var(rs, CONFIG_VAR_NAME) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Failed to read {}: {:?}", CONFIG_VAR_NAME, e);
+            None
+        }
+    };
+    if let Some(data) = data {
+        match parse_config_var(CONFIG_VAR_NAME, &data, system, pending) {
+            Ok(config) => {
+                info!("Applying {}", CONFIG_VAR_NAME);
+                // only rewrite the good copy when it changed, NV writes wear flash
+                let good = read_jumpstart_var(rs, CONFIG_GOOD_VAR_NAME).ok().flatten();
+                if good.as_deref() != Some(&data[..]) {
+                    pending.good_copy = Some(data);
+                }
+                return Some(config);
+            }
+            Err(e) => warn!("Ignoring invalid {}: {:?}", CONFIG_VAR_NAME, e),
+        }
+    }
+    let good = match read_jumpstart_var(rs, CONFIG_GOOD_VAR_NAME) {
+        Ok(good) => good?,
+        Err(e) => {
+            warn!("Failed to read {}: {:?}", CONFIG_GOOD_VAR_NAME, e);
+            return None;
+        }
+    };
+    match parse_config_var(CONFIG_GOOD_VAR_NAME, &good, system, pending) {
+        Ok(config) => {
+            info!("Applying last known good {}", CONFIG_GOOD_VAR_NAME);
+            Some(config)
+        }
+        Err(e) => {
+            warn!("Ignoring invalid {}: {:?}", CONFIG_GOOD_VAR_NAME, e);
+            None
+        }
+    }
+}
+
+// JumpstartConfigOnce is deleted before anything is booted with it, so a
+// config that hangs the boot is gone after the next reset
+fn read_config_once_var(
+    rs: &RuntimeServices,
+    system: Option<&SystemInfo>,
+    pending: &mut Pending,
+) -> Option<Config> {
+    let data = match read_jumpstart_var(rs, CONFIG_ONCE_VAR_NAME) {
+        Ok(data) => data?,
+        Err(e) => {
+            warn!("Failed to read {}: {:?}", CONFIG_ONCE_VAR_NAME, e);
+            return None;
+        }
+    };
+    pending.delete_once = true;
+    match parse_config_var(CONFIG_ONCE_VAR_NAME, &data, system, pending) {
+        Ok(config) => {
+            info!("Applying {}", CONFIG_ONCE_VAR_NAME);
+            Some(config)
+        }
+        Err(e) => {
+            warn!("Ignoring invalid {}: {:?}", CONFIG_ONCE_VAR_NAME, e);
+            None
+        }
+    }
+}
+
+// Only the default config file may be missing
+fn read_config_file(
+    bs: &BootServices,
+    file: Option<&str>,
+    pending: &mut Pending,
+) -> Result<Option<String>> {
+    let mut fs = FileSystem::new(
+        bs.get_image_file_system(bs.image_handle())
+            .map_err(anyhow::Error::msg)?,
+    );
+    let config_path = match file {
+        Some(file) => user_path(bs, file)?,
+        None => jumpstart_path(bs, CONFIG_FILE_NAME)?,
+    };
+    let path = Path::new(&config_path);
+    if !fs.try_exists(path).map_err(anyhow::Error::msg)? {
+        if file.is_some() {
+            return Err(anyhow!("config file {} not found", config_path));
+        }
+        info!("No config file at {}", config_path);
+        return Ok(None);
+    }
+    let text = fs.read_to_string(path).map_err(anyhow::Error::msg)?;
+    pending.measure(text.as_bytes(), format!("config {}", config_path));
+    Ok(Some(text))
+}
+
+fn read_oem_strings(system: Option<&SystemInfo>, pending: &mut Pending) -> Option<Config> {
+    let strings: Vec<&str> = system
+        .map(|s| s.oem_strings.iter())
+        .into_iter()
+        .flatten()
+        .filter_map(|s| s.strip_prefix(OEM_STRING_PREFIX))
+        .collect();
+    if strings.is_empty() {
+        return None;
+    }
+    let text = strings.join("\n");
+    info!("SMBIOS OEM config: '{}'", strings.join(" "));
+    pending.measure(text.as_bytes(), "SMBIOS OEM strings".to_string());
+    // like the other layers, a bad string only costs the OEM settings
+    match Config::parse_oem_strings(&strings) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!("Ignoring invalid SMBIOS OEM strings: {:?}", e);
+            None
+        }
+    }
+}
+
+fn read_load_options(bs: &BootServices) -> Result<Option<String>> {
+    let loaded_image = bs
+        .open_protocol_exclusive::<LoadedImage>(bs.image_handle())
+        .map_err(anyhow::Error::msg)?;
+    let Some(data) = loaded_image.load_options_as_bytes() else {
+        return Ok(None);
+    };
+    let text = match decode_load_options(data)? {
+        Some(text) => text,
+        None => match loaded_image.load_options_as_cstr16() {
+            Ok(s) => s.to_string(),
+            Err(_) => {
+                warn!("Ignoring load options that are neither UCS-2 nor jumpstart's binary format");
+                return Ok(None);
+            }
+        },
+    };
+    Ok(Some(text).filter(|s| !s.trim().is_empty()))
+}
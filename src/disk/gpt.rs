@@ -0,0 +1,49 @@
+extern crate alloc;
+
+use anyhow::Result;
+use bootmgr::gpt::BlockReader;
+use uefi::{
+    proto::media::{block::BlockIO, disk::DiskIo},
+    table::boot::{BootServices, ScopedProtocol},
+    Handle,
+};
+
+use crate::open_protocol_shared;
+
+// BlockIO gives us the media geometry, DiskIo does unaligned reads for us
+pub struct BlockDevice<'a> {
+    block_io: ScopedProtocol<'a, BlockIO>,
+    disk_io: ScopedProtocol<'a, DiskIo>,
+}
+
+impl<'a> BlockDevice<'a> {
+    pub fn open(bs: &'a BootServices, handle: Handle) -> Result<Self> {
+        Ok(BlockDevice {
+            block_io: open_protocol_shared::<BlockIO>(bs, handle)?,
+            disk_io: open_protocol_shared::<DiskIo>(bs, handle)?,
+        })
+    }
+
+    // true for whole disks, false for partitions produced by the partition driver
+    pub fn is_whole_disk(&self) -> bool {
+        let media = self.block_io.media();
+        media.is_media_present() && !media.is_logical_partition()
+    }
+}
+
+impl BlockReader for BlockDevice<'_> {
+    fn block_size(&self) -> u32 {
+        self.block_io.media().block_size()
+    }
+
+    fn last_block(&self) -> u64 {
+        self.block_io.media().last_block()
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        let media_id = self.block_io.media().media_id();
+        self.disk_io
+            .read_disk(media_id, offset, buffer)
+            .map_err(anyhow::Error::msg)
+    }
+}
@@ -0,0 +1,228 @@
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::Result;
+use bootmgr::{
+    boot_vars::{EfiBootManager, LoadOptionType},
+    config::Config,
+    gpt::GptDisk,
+    scan::{match_nvme_boot_option, Candidate},
+};
+use log::info;
+use uefi::{
+    fs::{FileSystem, Path},
+    proto::device_path::{
+        text::{AllowShortcuts, DisplayOnly},
+        DevicePath,
+    },
+    table::{boot::BootServices, runtime::RuntimeServices},
+};
+use uefi_services::println;
+
+use crate::{
+    check_image, get_image_fs, is_last_boot_current,
+    last_boot::LastBoot,
+    secure_boot::SecureBootState,
+    selection::{self, Selection},
+    sync,
+    trust::TrustStore,
+    user_path,
+};
+
+// Dry run report: what jumpstart would boot and why, in the order the real
+// boot tries things. Only the drivers are loaded and started, they are
+// needed to see the disks. Images are read and verified but not loaded, so
+// neither jumpstart nor the firmware measures them, and no state variable
+// is written.
+
+// Report lines go to the console right away and are kept for the file
+#[derive(Default)]
+struct Report {
+    lines: Vec<String>,
+}
+
+impl Report {
+    fn line(&mut self, line: String) {
+        println!("{}", line);
+        self.lines.push(line);
+    }
+
+    fn section(&mut self, title: &str) {
+        self.line(String::new());
+        self.line(format!("== {} ==", title));
+    }
+
+    fn write(&self, bs: &BootServices, file: &str) -> Result<()> {
+        let path = user_path(bs, file)?;
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        FileSystem::new(get_image_fs(bs)?)
+            .write(Path::new(&path), text.as_bytes())
+            .map_err(anyhow::Error::msg)?;
+        info!("Dry run report written to {}", path);
+        Ok(())
+    }
+}
+
+fn path_string(bs: &BootServices, device_path: &DevicePath) -> String {
+    device_path
+        .to_string(bs, DisplayOnly(false), AllowShortcuts(false))
+        .map(|s| s.to_string())
+        .unwrap_or_else(|_| "<unprintable device path>".to_string())
+}
+
+fn boot_entries(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    fs_device_paths: &[Box<DevicePath>],
+    report: &mut Report,
+) -> Result<()> {
+    report.section("Boot#### entries");
+    let boot_mgr = EfiBootManager::new_from_variables(rs, LoadOptionType::Boot)?;
+    report.line(format!(
+        "BootOrder: {:04X?}",
+        boot_mgr.boot_order.boot_order
+    ));
+    for (index, option) in boot_mgr.boot_options.iter() {
+        report.line(format!("Boot{:04X} '{}'", index, option.description));
+        report.line(format!("  {}", option.attributes));
+        for (i, p) in option.device_path_list.iter().enumerate() {
+            report.line(format!("  device path {}: {}", i, path_string(bs, p)));
+        }
+        if sync::is_companion(option) {
+            report.line("  jumpstart companion entry".to_string());
+        }
+        match match_nvme_boot_option(option, fs_device_paths) {
+            Ok(device_paths) => {
+                if device_paths.len() > 1 {
+                    report.line(format!(
+                        "  {} NVMe filesystems have this partition, cloned disk?",
+                        device_paths.len()
+                    ));
+                }
+                for device_path in device_paths {
+                    report.line(format!(
+                        "  matched NVMe filesystem: {}",
+                        path_string(bs, &device_path)
+                    ));
+                }
+            }
+            Err(e) => report.line(format!("  not matched: {}", e)),
+        }
+    }
+    Ok(())
+}
+
+fn cached_path(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    config: &Config,
+    report: &mut Report,
+) -> Option<Candidate<Selection>> {
+    match LastBoot::load(rs) {
+        Ok(Some(last_boot)) if is_last_boot_current(bs, rs, config, &last_boot) => {
+            report.line(format!(
+                "cached boot path: current, from {}",
+                last_boot.source
+            ));
+            Some(Candidate {
+                source: format!("cached boot path ({})", last_boot.source),
+                device_path: last_boot.device_path,
+                selection: Selection::CachedPath,
+            })
+        }
+        Ok(Some(last_boot)) => {
+            report.line(format!(
+                "cached boot path: stale, from {}",
+                last_boot.source
+            ));
+            None
+        }
+        Ok(None) => {
+            report.line("cached boot path: none".to_string());
+            None
+        }
+        Err(e) => {
+            report.line(format!("cached boot path: unreadable: {}", e));
+            None
+        }
+    }
+}
+
+// Print the report and write it to the configured file
+pub fn run(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    config: &Config,
+    fs_device_paths: &[Box<DevicePath>],
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+    trust: Option<&TrustStore>,
+) -> Result<()> {
+    let mut report = Report::default();
+    report.line("jumpstart dry run".to_string());
+    report.line(format!("Secure Boot: {}", SecureBootState::read(rs)));
+    report.line(format!("NVMe filesystems: {}", fs_device_paths.len()));
+    for path in fs_device_paths {
+        report.line(format!("  {}", path_string(bs, path)));
+    }
+
+    boot_entries(bs, rs, fs_device_paths, &mut report)?;
+
+    report.section("Selection");
+    let mut candidates = Vec::new();
+    candidates.extend(cached_path(bs, rs, config, &mut report));
+    // a copy of the slot state, only the real boot consumes tries
+    let slot_state = selection::load_slot_state(rs, config)?;
+    candidates.extend(selection::candidates(
+        bs,
+        rs,
+        config,
+        fs_device_paths,
+        gpt_disks,
+        slot_state.as_ref(),
+        &mut |line| report.line(line),
+    )?);
+
+    report.section("Candidates");
+    for (n, candidate) in candidates.iter().enumerate() {
+        report.line(format!(
+            "{}. {}: {}",
+            n + 1,
+            candidate.source,
+            path_string(bs, &candidate.device_path)
+        ));
+    }
+
+    report.section("Result");
+    let mut chosen = false;
+    for candidate in candidates.iter() {
+        match check_image(bs, &candidate.device_path, trust) {
+            Ok(()) => {
+                report.line(format!(
+                    "would start {}: {}",
+                    candidate.source,
+                    path_string(bs, &candidate.device_path)
+                ));
+                chosen = true;
+                break;
+            }
+            Err(e) => report.line(format!("{} would fail: {}", candidate.source, e)),
+        }
+    }
+    if !chosen {
+        match config.final_action {
+            Some(action) => report.line(format!("nothing would boot, final action {}", action)),
+            None => report.line("nothing would boot".to_string()),
+        }
+    }
+
+    if let Some(file) = &config.report_file {
+        report.write(bs, file)?;
+    }
+    Ok(())
+}
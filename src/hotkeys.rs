@@ -0,0 +1,258 @@
+extern crate alloc;
+
+use core::ffi::c_void;
+
+use alloc::{string::ToString, vec::Vec};
+use anyhow::{anyhow, Result};
+use bootmgr::{boot_vars::LoadOptionType, crc32::crc32};
+use log::{info, warn};
+use regex::Regex;
+use uefi::{
+    proto::unsafe_protocol,
+    table::{
+        boot::{BootServices, ScopedProtocol},
+        runtime::{RuntimeServices, VariableVendor},
+    },
+    Status, StatusExt,
+};
+
+use crate::open_protocol_shared;
+
+// Key#### hotkeys, UEFI spec 3.1.6. Each variable holds an EFI_KEY_OPTION:
+//   u32 KeyData (bits 0-7 revision, 8-13 shift/control/alt/logo/menu/sysreq
+//   pressed, 30-31 number of input keys), u32 CRC32 of the Boot#### variable,
+//   u16 Boot#### index, then up to 3 EFI_INPUT_KEYs (u16 scan code, u16 char)
+const KEY_OPTION_HEADER_SIZE: usize = 10;
+const INPUT_KEY_SIZE: usize = 4;
+
+// KeyData modifier bits and the matching EFI_KEY_STATE.KeyShiftState bits
+// (right | left variant)
+const MODIFIERS: [(u32, u32); 6] = [
+    (1 << 8, 0x0000_0003),  // shift
+    (1 << 9, 0x0000_000c),  // control
+    (1 << 10, 0x0000_0030), // alt
+    (1 << 11, 0x0000_00c0), // logo
+    (1 << 12, 0x0000_0100), // menu
+    (1 << 13, 0x0000_0200), // sysreq
+];
+const SHIFT_STATE_VALID: u32 = 0x8000_0000;
+
+// EFI_INPUT_KEY scan codes and characters the menu understands
+pub const SCAN_UP: u16 = 0x01;
+pub const SCAN_DOWN: u16 = 0x02;
+pub const SCAN_F2: u16 = 0x0c;
+pub const SCAN_ESC: u16 = 0x17;
+pub const CHAR_CARRIAGE_RETURN: u16 = 0x0d;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct InputKey {
+    pub scan_code: u16,
+    pub unicode_char: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct KeyData {
+    pub key: InputKey,
+    pub key_shift_state: u32,
+    pub key_toggle_state: u8,
+}
+
+// EFI_SIMPLE_TEXT_INPUT_EX_PROTOCOL, only what we need of it. Plain
+// SimpleTextInput doesn't report modifier keys
+#[repr(C)]
+#[unsafe_protocol("dd9e7534-7762-4698-8c14-f58517a625aa")]
+pub struct TextInputEx {
+    reset: unsafe extern "efiapi" fn(this: *mut TextInputEx, extended_verification: bool) -> Status,
+    read_key_stroke_ex:
+        unsafe extern "efiapi" fn(this: *mut TextInputEx, key_data: *mut KeyData) -> Status,
+    wait_for_key_ex: *mut c_void,
+    set_state: *mut c_void,
+    register_key_notify: *mut c_void,
+    unregister_key_notify: *mut c_void,
+}
+
+impl TextInputEx {
+    // Next keystroke, None if no key is waiting
+    pub fn read_key_stroke(&mut self) -> uefi::Result<Option<KeyData>> {
+        let mut key_data = KeyData {
+            key: InputKey {
+                scan_code: 0,
+                unicode_char: 0,
+            },
+            key_shift_state: 0,
+            key_toggle_state: 0,
+        };
+        match unsafe { (self.read_key_stroke_ex)(self, &mut key_data) } {
+            Status::NOT_READY => Ok(None),
+            status => status.to_result_with_val(|| Some(key_data)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyOption {
+    pub key_data: u32,
+    pub boot_option_crc: u32,
+    pub boot_option: u16,
+    pub keys: Vec<InputKey>,
+}
+
+impl TryFrom<&[u8]> for KeyOption {
+    type Error = anyhow::Error;
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < KEY_OPTION_HEADER_SIZE {
+            return Err(anyhow!("Key#### data too short"));
+        }
+        let key_data = u32::from_ne_bytes(data[0..4].try_into().unwrap());
+        let boot_option_crc = u32::from_ne_bytes(data[4..8].try_into().unwrap());
+        let boot_option = u16::from_ne_bytes([data[8], data[9]]);
+        let count = (key_data >> 30) as usize;
+        let keys_data = data
+            .get(KEY_OPTION_HEADER_SIZE..KEY_OPTION_HEADER_SIZE + count * INPUT_KEY_SIZE)
+            .ok_or_else(|| anyhow!("Key#### has fewer keys than InputKeyCount {}", count))?;
+        let keys = keys_data
+            .chunks(INPUT_KEY_SIZE)
+            .map(|k| InputKey {
+                scan_code: u16::from_ne_bytes([k[0], k[1]]),
+                unicode_char: u16::from_ne_bytes([k[2], k[3]]),
+            })
+            .collect();
+        Ok(KeyOption {
+            key_data,
+            boot_option_crc,
+            boot_option,
+            keys,
+        })
+    }
+}
+
+impl KeyOption {
+    // Modifiers pressed with the key must be exactly the ones required.
+    // Without shift state information only hotkeys without modifiers match
+    fn modifiers_match(&self, key_shift_state: u32) -> bool {
+        if key_shift_state & SHIFT_STATE_VALID == 0 {
+            return MODIFIERS.iter().all(|(bit, _)| self.key_data & bit == 0);
+        }
+        MODIFIERS
+            .iter()
+            .all(|(bit, state)| (self.key_data & bit != 0) == (key_shift_state & state != 0))
+    }
+}
+
+// Open the console's extended text input
+pub fn open_text_input_ex(bs: &BootServices) -> Result<ScopedProtocol<'_, TextInputEx>> {
+    let handle = bs
+        .get_handle_for_protocol::<TextInputEx>()
+        .map_err(anyhow::Error::msg)?;
+    open_protocol_shared::<TextInputEx>(bs, handle)
+}
+
+// What was pressed during the startup window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupKey {
+    // a Key#### hotkey, with its Boot#### index
+    Hotkey(u16),
+    // F2
+    FirmwareSetup,
+    // Esc
+    Menu,
+}
+
+// Hotkeys bound to existing Boot#### entries, with the progress through
+// their key sequence
+pub struct Hotkeys {
+    options: Vec<KeyOption>,
+    progress: Vec<usize>,
+}
+
+impl Hotkeys {
+    // Read all Key#### variables. Options whose CRC doesn't match the
+    // current Boot#### contents are stale and skipped, as the spec requires
+    pub fn load(rs: &RuntimeServices) -> Result<Self> {
+        let re = Regex::new(r"^Key[0-9A-Fa-f]{4}$").unwrap();
+        let mut options = Vec::new();
+        for key in rs.variable_keys().map_err(anyhow::Error::msg)? {
+            if key.vendor != VariableVendor::GLOBAL_VARIABLE {
+                continue;
+            }
+            let name = key.name().map_err(anyhow::Error::msg)?;
+            let name_str = name.to_string();
+            if !re.is_match(&name_str) {
+                continue;
+            }
+            let (value, _) = rs
+                .get_variable_boxed(name, &VariableVendor::GLOBAL_VARIABLE)
+                .map_err(anyhow::Error::msg)?;
+            let option = match KeyOption::try_from(value.as_ref()) {
+                Ok(option) => option,
+                Err(e) => {
+                    warn!("Skipping invalid {}: {:?}", name_str, e);
+                    continue;
+                }
+            };
+            if option.keys.is_empty() {
+                info!(
+                    "Skipping {}: modifier-only hotkeys are not supported",
+                    name_str
+                );
+                continue;
+            }
+            let boot_var = LoadOptionType::Boot.var_name(option.boot_option as usize)?;
+            match rs.get_variable_boxed(&boot_var, &VariableVendor::GLOBAL_VARIABLE) {
+                Ok((boot_data, _)) if crc32(&boot_data) == option.boot_option_crc => {
+                    info!("{} -> {}", name_str, boot_var);
+                    options.push(option);
+                }
+                Ok(_) => warn!("Skipping {}: CRC doesn't match {}", name_str, boot_var),
+                Err(e) => warn!("Skipping {}: {} unreadable: {:?}", name_str, boot_var, e),
+            }
+        }
+        let progress = alloc::vec![0; options.len()];
+        Ok(Hotkeys { options, progress })
+    }
+
+    // Feed one keystroke, returns the Boot#### index once a whole key
+    // sequence was typed
+    fn feed(&mut self, key_data: &KeyData) -> Option<u16> {
+        for (option, progress) in self.options.iter().zip(self.progress.iter_mut()) {
+            let matches = |position: usize| {
+                option.keys[position] == key_data.key
+                    && option.modifiers_match(key_data.key_shift_state)
+            };
+            if matches(*progress) {
+                *progress += 1;
+            } else {
+                // the key may start the sequence over, e.g. A,A,B for A,B
+                *progress = matches(0) as usize;
+            }
+            if *progress == option.keys.len() {
+                *progress = 0;
+                return Some(option.boot_option);
+            }
+        }
+        None
+    }
+
+    // Poll the keyboard for `window_ms`. Returns the first hotkey typed.
+    // Key#### bindings take precedence over the built-in F2/Esc keys
+    pub fn wait(&mut self, bs: &BootServices, window_ms: u64) -> Result<Option<StartupKey>> {
+        let mut input = open_text_input_ex(bs)?;
+        info!("Waiting {} ms for hotkeys", window_ms);
+        for _ in 0..window_ms.div_ceil(10) {
+            while let Some(key_data) = input.read_key_stroke().map_err(anyhow::Error::msg)? {
+                if let Some(boot_option) = self.feed(&key_data) {
+                    return Ok(Some(StartupKey::Hotkey(boot_option)));
+                }
+                match key_data.key.scan_code {
+                    SCAN_F2 => return Ok(Some(StartupKey::FirmwareSetup)),
+                    SCAN_ESC => return Ok(Some(StartupKey::Menu)),
+                    _ => {}
+                }
+            }
+            bs.stall(10_000);
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,114 @@
+extern crate alloc;
+
+use alloc::{format, vec::Vec};
+use anyhow::{Context, Result};
+use bootmgr::{
+    boot_vars::{
+        EfiBootManager, EfiLoadOption, LoadOptionAttributes, LoadOptionAttributesBits,
+        LoadOptionType,
+    },
+    crypto::sha256,
+};
+use log::info;
+use uefi::{
+    cstr16,
+    fs::{FileSystem, Path},
+    table::{boot::BootServices, runtime::RuntimeServices},
+    CStr16,
+};
+
+use crate::{get_image_fs, get_nvme_driver_device_path, jumpstart_path};
+
+// Install mode registers the NVMe driver with the firmware as a Driver####
+// load option, so the firmware loads it before boot options are processed
+// and native Boot#### entries on NVMe work without jumpstart. The driver is
+// copied out of the jumpstart directory so the entry survives jumpstart
+// being moved or removed.
+const INSTALLED_DRIVER_DIR: &CStr16 = cstr16!(r"EFI\jumpstart");
+const INSTALLED_DRIVER_PATH: &CStr16 = cstr16!(r"EFI\jumpstart\NvmExpressDxe.efi");
+const DRIVER_DESCRIPTION: &CStr16 = cstr16!("jumpstart NVMe driver");
+
+// Our Driver#### is recognised by its description
+fn is_ours(option: &EfiLoadOption) -> bool {
+    option.description.as_ref() == DRIVER_DESCRIPTION
+}
+
+// Copy the driver next to the ESP root unless an identical copy is there
+fn copy_driver(bs: &BootServices, driver_file: &str) -> Result<()> {
+    let mut fs = FileSystem::new(get_image_fs(bs)?);
+    let source = jumpstart_path(bs, driver_file)?;
+    let data = fs
+        .read(Path::new(&source))
+        .map_err(anyhow::Error::msg)
+        .with_context(|| format!("failed to read {}", source))?;
+
+    let target = Path::new(INSTALLED_DRIVER_PATH);
+    if fs.try_exists(target).map_err(anyhow::Error::msg)?
+        && sha256(&fs.read(target).map_err(anyhow::Error::msg)?) == sha256(&data)
+    {
+        info!("{} is up to date", INSTALLED_DRIVER_PATH);
+        return Ok(());
+    }
+    fs.create_dir_all(Path::new(INSTALLED_DRIVER_DIR))
+        .map_err(anyhow::Error::msg)?;
+    fs.write(target, &data)
+        .map_err(anyhow::Error::msg)
+        .with_context(|| format!("failed to write {}", INSTALLED_DRIVER_PATH))?;
+    info!("Copied {} to {}", source, INSTALLED_DRIVER_PATH);
+    Ok(())
+}
+
+// Copy the driver, create or update our Driver#### and put it in DriverOrder
+pub fn install_driver(bs: &BootServices, rs: &RuntimeServices, driver_file: &str) -> Result<()> {
+    copy_driver(bs, driver_file)?;
+
+    let mut drivers = EfiBootManager::new_from_variables(rs, LoadOptionType::Driver)?;
+    let index = match drivers
+        .boot_options
+        .iter()
+        .find(|(_, option)| is_ours(option))
+    {
+        Some((index, _)) => *index,
+        None => drivers.get_next_available_boot_index()?,
+    };
+
+    let option = EfiLoadOption {
+        attributes: LoadOptionAttributes::from(
+            LoadOptionAttributesBits::LoadOptionActive as u32
+                | LoadOptionAttributesBits::LoadOptionForceReconnect as u32,
+        ),
+        description: DRIVER_DESCRIPTION.into(),
+        device_path_list: alloc::vec![get_nvme_driver_device_path(bs, INSTALLED_DRIVER_PATH)?],
+        optional_data: None,
+    };
+    drivers.set_option(rs, index, option)?;
+    drivers.add_to_order(rs, index, false)?;
+    info!(
+        "Installed Driver{:04X}, DriverOrder: {:04X?}",
+        index, drivers.boot_order.boot_order
+    );
+    Ok(())
+}
+
+// Remove our Driver#### entries, their DriverOrder slots and the driver copy
+pub fn uninstall_driver(bs: &BootServices, rs: &RuntimeServices) -> Result<()> {
+    let mut drivers = EfiBootManager::new_from_variables(rs, LoadOptionType::Driver)?;
+    let ours: Vec<usize> = drivers
+        .boot_options
+        .iter()
+        .filter(|(_, option)| is_ours(option))
+        .map(|(index, _)| *index)
+        .collect();
+    for index in ours {
+        drivers.delete_option(rs, index)?;
+        info!("Removed Driver{:04X}", index);
+    }
+
+    let mut fs = FileSystem::new(get_image_fs(bs)?);
+    let target = Path::new(INSTALLED_DRIVER_PATH);
+    if fs.try_exists(target).map_err(anyhow::Error::msg)? {
+        fs.remove_file(target).map_err(anyhow::Error::msg)?;
+        info!("Removed {}", INSTALLED_DRIVER_PATH);
+    }
+    Ok(())
+}
@@ -0,0 +1,274 @@
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::Result;
+use bootmgr::{
+    boot_vars::{EfiBootManager, LoadOptionType},
+    config::Config,
+    crypto::to_hex,
+    device_path::DevicePathExt,
+    smbios::SystemInfo,
+};
+use log::{info, warn};
+use uefi::{
+    fs::{FileSystem, Path},
+    proto::{
+        device_path::{
+            text::{AllowShortcuts, DisplayOnly},
+            DevicePath,
+        },
+        media::{block::BlockIO, fs::SimpleFileSystem},
+    },
+    table::{
+        boot::{BootServices, SearchType},
+        runtime::RuntimeServices,
+    },
+    Handle, Identify,
+};
+
+use crate::{
+    get_all_block_device_paths, get_all_device_paths_for_protocol, get_all_disk_device_paths,
+    get_all_handles_for_protocol, get_device_path_boxed, get_image_fs,
+    json::{Object, Value},
+    open_protocol_shared,
+    secure_boot::SecureBootState,
+    smbios::Smbios,
+    user_path,
+};
+
+// Inventory mode writes what jumpstart can see of the machine to a JSON file
+// on the ESP, for bringing up new models
+pub const INVENTORY_FILE_NAME: &str = "inventory.json";
+
+fn path_string(bs: &BootServices, device_path: &DevicePath) -> Value {
+    device_path
+        .to_string(bs, DisplayOnly(false), AllowShortcuts(false))
+        .map(|s| Value::from(s.to_string()))
+        .unwrap_or(Value::Null)
+}
+
+fn paths(bs: &BootServices, device_paths: &[Box<DevicePath>]) -> Value {
+    Value::Array(device_paths.iter().map(|p| path_string(bs, p)).collect())
+}
+
+fn handle_id(handle: Handle) -> String {
+    format!("{:#x}", handle.as_ptr() as usize)
+}
+
+fn firmware() -> Value {
+    let st = uefi_services::system_table();
+    Object::new()
+        .field("vendor", st.firmware_vendor().to_string())
+        .field("revision", format!("{:#x}", st.firmware_revision()))
+        .field("uefi_version", st.uefi_revision().to_string())
+        .into()
+}
+
+fn smbios() -> Value {
+    let smbios = match Smbios::find() {
+        Ok(Some(smbios)) => smbios,
+        Ok(None) => return Value::Null,
+        Err(e) => {
+            warn!("Failed to read SMBIOS: {:?}", e);
+            return Value::Null;
+        }
+    };
+    let info = SystemInfo::read(&smbios.structures());
+    Object::new()
+        .field(
+            "version",
+            format!("{}.{}", smbios.version.0, smbios.version.1),
+        )
+        .field("bios_vendor", info.bios_vendor)
+        .field("bios_version", info.bios_version)
+        .field("bios_date", info.bios_date)
+        .field("manufacturer", info.manufacturer)
+        .field("product", info.product)
+        .field("version", info.version)
+        .field("serial", info.serial)
+        .field("uuid", info.uuid.map(|u| u.to_string()))
+        .field("sku", info.sku)
+        .field("family", info.family)
+        .field("oem_strings", info.oem_strings)
+        .field(
+            "structures",
+            smbios
+                .structures()
+                .iter()
+                .map(|s| {
+                    Object::new()
+                        .field("type", u32::from(s.kind))
+                        .field("handle", u32::from(s.handle))
+                        .field("length", s.data.len())
+                        .into()
+                })
+                .collect::<Vec<Value>>(),
+        )
+        .into()
+}
+
+fn secure_boot(rs: &RuntimeServices) -> Value {
+    let state = SecureBootState::read(rs);
+    Object::new()
+        .field("secure_boot", state.secure_boot)
+        .field("setup_mode", state.setup_mode)
+        .field("audit_mode", state.audit_mode)
+        .field("deployed_mode", state.deployed_mode)
+        .field("enforcing", state.is_enforcing())
+        .into()
+}
+
+fn handles(bs: &BootServices) -> Result<Value> {
+    let handles = bs
+        .locate_handle_buffer(SearchType::AllHandles)
+        .map_err(anyhow::Error::msg)?;
+    let mut values = Vec::new();
+    for handle in handles.iter() {
+        let protocols: Vec<String> = match bs.protocols_per_handle(*handle) {
+            Ok(protocols) => protocols.iter().map(|g| g.to_string()).collect(),
+            Err(e) => {
+                warn!("Failed to get protocols of {:?}: {:?}", handle, e);
+                Vec::new()
+            }
+        };
+        values.push(
+            Object::new()
+                .field("handle", handle_id(*handle))
+                .field("protocols", protocols)
+                .field(
+                    "device_path",
+                    get_device_path_boxed(bs, *handle)
+                        .map(|p| path_string(bs, &p))
+                        .unwrap_or(Value::Null),
+                )
+                .into(),
+        );
+    }
+    Ok(Value::Array(values))
+}
+
+fn block_media(bs: &BootServices) -> Result<Value> {
+    let mut values = Vec::new();
+    for handle in get_all_handles_for_protocol(bs, &BlockIO::GUID)? {
+        let Ok(block_io) = open_protocol_shared::<BlockIO>(bs, handle) else {
+            continue;
+        };
+        let media = block_io.media();
+        let device_path = get_device_path_boxed(bs, handle).ok();
+        values.push(
+            Object::new()
+                .field("handle", handle_id(handle))
+                .field(
+                    "device_path",
+                    device_path
+                        .as_ref()
+                        .map(|p| path_string(bs, p))
+                        .unwrap_or(Value::Null),
+                )
+                .field("nvme", device_path.as_ref().is_some_and(|p| p.is_nvme()))
+                .field("media_id", media.media_id())
+                .field("removable", media.is_removable_media())
+                .field("present", media.is_media_present())
+                .field("partition", media.is_logical_partition())
+                .field("read_only", media.is_read_only())
+                .field("block_size", media.block_size())
+                .field("last_block", media.last_block())
+                .into(),
+        );
+    }
+    Ok(Value::Array(values))
+}
+
+fn load_options(bs: &BootServices, rs: &RuntimeServices) -> Value {
+    let mut families = Vec::new();
+    for option_type in [
+        LoadOptionType::Boot,
+        LoadOptionType::Driver,
+        LoadOptionType::SysPrep,
+        LoadOptionType::PlatformRecovery,
+    ] {
+        let manager = match EfiBootManager::new_from_variables(rs, option_type) {
+            Ok(manager) => manager,
+            Err(e) => {
+                warn!(
+                    "Failed to read {}#### options: {:?}",
+                    option_type.prefix(),
+                    e
+                );
+                continue;
+            }
+        };
+        let options: Vec<Value> = manager
+            .boot_options
+            .iter()
+            .map(|(index, option)| {
+                Object::new()
+                    .field("index", *index)
+                    .field("description", option.description.to_string())
+                    .field("attributes", u32::from(option.attributes))
+                    .field("active", option.is_active())
+                    .field("device_paths", paths(bs, &option.device_path_list))
+                    .field("optional_data", option.optional_data.as_deref().map(to_hex))
+                    .into()
+            })
+            .collect();
+        families.push((
+            option_type.prefix().to_string(),
+            Object::new()
+                .field(
+                    "order",
+                    // PlatformRecovery#### has no order variable
+                    (option_type != LoadOptionType::PlatformRecovery).then(|| {
+                        Value::from(
+                            manager
+                                .boot_order
+                                .boot_order
+                                .iter()
+                                .map(|i| u32::from(*i))
+                                .collect::<Vec<_>>(),
+                        )
+                    }),
+                )
+                .field("options", options)
+                .into(),
+        ));
+    }
+    Value::Object(families)
+}
+
+// Collect the inventory and write it to the configured file
+pub fn run(bs: &BootServices, rs: &RuntimeServices, config: &Config) -> Result<()> {
+    let inventory: Value = Object::new()
+        .field("firmware", firmware())
+        .field("smbios", smbios())
+        .field("secure_boot", secure_boot(rs))
+        .field("handles", handles(bs)?)
+        .field("block_devices", paths(bs, &get_all_block_device_paths(bs)?))
+        .field("block_media", block_media(bs)?)
+        .field("disks", paths(bs, &get_all_disk_device_paths(bs)?))
+        .field(
+            "filesystems",
+            paths(
+                bs,
+                &get_all_device_paths_for_protocol::<SimpleFileSystem>(bs)?,
+            ),
+        )
+        .field("load_options", load_options(bs, rs))
+        .into();
+
+    let file = config
+        .inventory_file
+        .as_deref()
+        .unwrap_or(INVENTORY_FILE_NAME);
+    let path = user_path(bs, file)?;
+    FileSystem::new(get_image_fs(bs)?)
+        .write(Path::new(&path), inventory.to_string().as_bytes())
+        .map_err(anyhow::Error::msg)?;
+    info!("Inventory written to {}", path);
+    Ok(())
+}
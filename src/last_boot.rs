@@ -0,0 +1,117 @@
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, Context, Result};
+use bootmgr::{
+    boot_vars::device_path_from_bytes,
+    device_path::DevicePathExt,
+    gpt::GptDisk,
+    jumpstart_vars::{read_jumpstart_var, write_jumpstart_var},
+};
+use uefi::{
+    cstr16,
+    proto::device_path::{media::PartitionSignature, DevicePath},
+    table::runtime::RuntimeServices,
+    CStr16, Guid,
+};
+
+// Full device path of the image jumpstart started last time, so the next boot
+// can connect just that controller instead of every handle in the system.
+//
+// Layout (version 1):
+//   u8 version, u8[3] reserved, disk GUID[16], partition GUID[16],
+//   u16 source length, source (UTF-8), device path
+pub const LAST_BOOT_VAR_NAME: &CStr16 = cstr16!("JumpstartLastBoot");
+const LAST_BOOT_VAR_VERSION: u8 = 1;
+const LAST_BOOT_HEADER_SIZE: usize = 38;
+
+pub struct LastBoot {
+    // GPT disk GUID of the disk holding the partition
+    pub disk_guid: Guid,
+    // GPT unique partition GUID, also found in the HardDrive node
+    pub partition_guid: Guid,
+    // what selected the image: `Boot0003` or a boot target from config
+    pub source: String,
+    pub device_path: Box<DevicePath>,
+}
+
+impl TryFrom<&[u8]> for LastBoot {
+    type Error = anyhow::Error;
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < LAST_BOOT_HEADER_SIZE {
+            return Err(anyhow!("JumpstartLastBoot data too short"));
+        }
+        if data[0] != LAST_BOOT_VAR_VERSION {
+            return Err(anyhow!("unsupported JumpstartLastBoot version {}", data[0]));
+        }
+        let disk_guid = Guid::from_bytes(data[4..20].try_into().unwrap());
+        let partition_guid = Guid::from_bytes(data[20..36].try_into().unwrap());
+        let source_len = u16::from_ne_bytes([data[36], data[37]]) as usize;
+        let source = data
+            .get(LAST_BOOT_HEADER_SIZE..LAST_BOOT_HEADER_SIZE + source_len)
+            .context("JumpstartLastBoot source truncated")?;
+        let source = core::str::from_utf8(source)
+            .map_err(anyhow::Error::msg)?
+            .to_string();
+        let device_path = device_path_from_bytes(&data[LAST_BOOT_HEADER_SIZE + source_len..])?;
+        Ok(LastBoot {
+            disk_guid,
+            partition_guid,
+            source,
+            device_path,
+        })
+    }
+}
+
+impl From<&LastBoot> for Vec<u8> {
+    fn from(data: &LastBoot) -> Vec<u8> {
+        let mut v = Vec::new();
+        v.extend_from_slice(&[LAST_BOOT_VAR_VERSION, 0, 0, 0]);
+        v.extend_from_slice(&data.disk_guid.to_bytes());
+        v.extend_from_slice(&data.partition_guid.to_bytes());
+        v.extend_from_slice(&(data.source.len() as u16).to_ne_bytes());
+        v.extend_from_slice(data.source.as_bytes());
+        v.extend_from_slice(data.device_path.as_bytes());
+        v
+    }
+}
+
+impl LastBoot {
+    // Identify the disk and partition the image is loaded from. Only images on
+    // GPT partitions of NVMe disks we have read can be cached
+    pub fn new(
+        device_path: &DevicePath,
+        source: &str,
+        gpt_disks: &[(Box<DevicePath>, GptDisk)],
+    ) -> Result<Self> {
+        let partition_guid = match device_path.hard_drive().map(|hd| hd.partition_signature()) {
+            Some(PartitionSignature::Guid(guid)) => guid,
+            _ => return Err(anyhow!("image is not on a GPT partition")),
+        };
+        let disk_guid = gpt_disks
+            .iter()
+            .find(|(_, gpt)| gpt.find_by_unique_guid(&partition_guid).is_some())
+            .map(|(_, gpt)| gpt.header.disk_guid)
+            .context("partition not found on any NVMe disk")?;
+        Ok(LastBoot {
+            disk_guid,
+            partition_guid,
+            source: source.to_string(),
+            device_path: device_path.to_boxed(),
+        })
+    }
+
+    pub fn load(rs: &RuntimeServices) -> Result<Option<Self>> {
+        read_jumpstart_var(rs, LAST_BOOT_VAR_NAME)?
+            .map(|data| LastBoot::try_from(data.as_ref()))
+            .transpose()
+    }
+
+    pub fn store(&self, rs: &RuntimeServices) -> Result<()> {
+        write_jumpstart_var(rs, LAST_BOOT_VAR_NAME, &Vec::from(self))
+    }
+}
@@ -1,17 +1,45 @@
 #![no_main]
 #![no_std]
-mod bootmgr;
+mod bls;
+mod config;
+mod disk;
+mod explain;
+mod hotkeys;
+mod install;
+mod inventory;
+mod json;
+mod last_boot;
+mod menu;
+mod power;
+mod secure_boot;
+mod selection;
+mod shim;
+mod smbios;
+mod sync;
+mod target;
+mod timing;
+mod tpm;
+mod trust;
 
 extern crate alloc;
 
-use alloc::{borrow::ToOwned, boxed::Box, format, vec::Vec};
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 use anyhow::{anyhow, Context, Result};
-use log::info;
+use log::{info, warn};
 use uefi::proto::{
-    self,
     device_path::{
         build::{self, DevicePathBuilder},
-        media::HardDrive,
         text::{AllowShortcuts, DisplayOnly},
         DevicePath, DevicePathNode, DevicePathNodeEnum, DeviceSubType, DeviceType,
         LoadedImageDevicePath,
@@ -20,10 +48,39 @@ use uefi::proto::{
     media::{block::BlockIO, disk::DiskIo, fs::SimpleFileSystem},
     ProtocolPointer,
 };
-use uefi::table::boot::{LoadImageSource, ScopedProtocol, SearchType};
-use uefi::{prelude::*, CString16, Guid};
+use uefi::table::boot::{
+    LoadImageSource, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol, SearchType,
+};
+use uefi::{
+    fs::{FileSystem, Path},
+    prelude::*,
+    CStr16, CString16, Guid, Identify,
+};
+
+use bootmgr::{
+    boot_vars::{EfiBootManager, EfiLoadOption, LoadOptionType},
+    config::{Config, ConnectStrategy, Mode, PowerAction},
+    crypto::{sha256, to_hex},
+    device_path::{
+        append_file_path, truncate_device_path, with_default_loader, DevicePathExt, PartialEqExt,
+    },
+    gpt::GptDisk,
+    scan::{self, Candidate, Firmware},
+    slots::SlotState,
+};
+use disk::gpt::BlockDevice;
+use hotkeys::{Hotkeys, StartupKey};
+use last_boot::LastBoot;
+use menu::MenuAction;
+use secure_boot::{describe_load_error, pe_has_signature, SecureBootState};
+use selection::Selection;
+use timing::Stopwatch;
+use trust::{TrustStore, SIGNATURE_SUFFIX};
 
-use bootmgr::boot_vars::EfiBootManager;
+// Where jumpstart looks for its files when its own path is unknown
+const DEFAULT_JUMPSTART_DIR: &str = r"efi\boot\js";
+// Set by `dry-run`: images are loaded and verified, but never started
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
 
 // Get the SimpleFileSystem for the current image handle
 fn get_image_fs(bs: &BootServices) -> Result<ScopedProtocol<SimpleFileSystem>> {
@@ -33,11 +90,59 @@ fn get_image_fs(bs: &BootServices) -> Result<ScopedProtocol<SimpleFileSystem>> {
     Ok(fs)
 }
 
-// Get the DevicePath for the NVME driver
-fn get_nvme_driver_device_path(bs: &BootServices) -> Result<Box<DevicePath>> {
+// All file path nodes of a device path joined into one path
+fn file_path_string(device_path: &DevicePath) -> Result<String> {
+    let mut path = String::new();
+    for node in device_path.node_iter() {
+        if node.full_type() != (DeviceType::MEDIA, DeviceSubType::MEDIA_FILE_PATH) {
+            continue;
+        }
+        let name = node.as_media_file_path()?.to_string();
+        if !path.is_empty() && !path.ends_with('\\') && !name.starts_with('\\') {
+            path.push('\\');
+        }
+        path.push_str(&name);
+    }
+    Ok(path)
+}
+
+// Directory with jumpstart's config and drivers: `js` next to our own image,
+// e.g. `\EFI\BOOT\js`, or `\EFI\fedora\js` when shim starts us as grubx64.efi
+fn jumpstart_dir(bs: &BootServices) -> Result<String> {
     let loaded_image = bs
         .open_protocol_exclusive::<LoadedImage>(bs.image_handle())
         .map_err(anyhow::Error::msg)?;
+    let image_path = file_path_string(
+        loaded_image
+            .file_path()
+            .context("loaded image has no file path")?,
+    )?;
+    let (dir, _) = image_path
+        .rsplit_once('\\')
+        .context("loaded image path has no directory")?;
+    Ok(format!(r"{}\js", dir.trim_start_matches('\\')))
+}
+
+// Path of a jumpstart file on the image's filesystem
+pub fn jumpstart_path(bs: &BootServices, file: &str) -> Result<CString16> {
+    let dir = jumpstart_dir(bs).unwrap_or_else(|e| {
+        warn!("Cannot tell where jumpstart was loaded from: {:?}", e);
+        DEFAULT_JUMPSTART_DIR.to_string()
+    });
+    CString16::try_from(format!(r"{}\{}", dir, file).as_str()).map_err(anyhow::Error::msg)
+}
+
+// Path of a file named by the user: relative to the jumpstart directory
+// unless it starts with `\`
+fn user_path(bs: &BootServices, file: &str) -> Result<CString16> {
+    match file.strip_prefix('\\') {
+        Some(path) => CString16::try_from(path).map_err(anyhow::Error::msg),
+        None => jumpstart_path(bs, file),
+    }
+}
+
+// Get the DevicePath for the NVME driver
+fn get_nvme_driver_device_path(bs: &BootServices, driver_path: &CStr16) -> Result<Box<DevicePath>> {
     let image_device_path = bs
         .open_protocol_exclusive::<LoadedImageDevicePath>(bs.image_handle())
         .expect("failed to open LoadedImageDevicePath protocol");
@@ -53,24 +158,53 @@ fn get_nvme_driver_device_path(bs: &BootServices) -> Result<Box<DevicePath>> {
     }
     builder = builder
         .push(&build::media::FilePath {
-            path_name: cstr16!(r"efi\boot\js\drivers\NvmExpressDxe.efi"),
+            path_name: driver_path,
         })
         .unwrap();
     Ok(builder.finalize().map_err(anyhow::Error::msg)?.to_owned())
 }
 
-// Load the NVME driver
-fn load_nvme_driver(boot_services: &BootServices) -> Result<Handle> {
-    let nvme_driver_device_path = get_nvme_driver_device_path(boot_services)?;
-    let nvme_image_handle = boot_services
-        .load_image(
-            boot_services.image_handle(),
-            LoadImageSource::FromDevicePath {
-                device_path: &nvme_driver_device_path,
-                from_boot_manager: false,
-            },
-        )
-        .map_err(anyhow::Error::msg)?;
+// Firmware may be configured to run anything from fixed media even with
+// Secure Boot on, so check for a signature ourselves before loading a driver
+fn check_driver_signature(
+    secure_boot: &SecureBootState,
+    data: &[u8],
+    driver_path: &str,
+) -> Result<()> {
+    if !secure_boot.is_enforcing() {
+        return Ok(());
+    }
+    if !pe_has_signature(data).with_context(|| driver_path.to_string())? {
+        return Err(anyhow!(
+            "refusing to load unsigned driver {} while Secure Boot is enabled",
+            driver_path
+        ));
+    }
+    Ok(())
+}
+
+// Load and start a driver from the jumpstart directory
+fn load_driver(
+    boot_services: &BootServices,
+    secure_boot: &SecureBootState,
+    trust: Option<&TrustStore>,
+    driver_file: &str,
+) -> Result<Handle> {
+    let driver_path = jumpstart_path(boot_services, driver_file)?;
+    let name = driver_path.to_string();
+    let nvme_driver_device_path = get_nvme_driver_device_path(boot_services, &driver_path)?;
+    // the signature check must see the very bytes that get loaded
+    let (data, signature) = read_image(boot_services, &nvme_driver_device_path, trust.is_some())
+        .with_context(|| format!("failed to read {}", name))?;
+    check_driver_signature(secure_boot, &data, &name)?;
+    let nvme_image_handle = load_image_from_buffer(
+        boot_services,
+        &nvme_driver_device_path,
+        &name,
+        &data,
+        signature.as_deref(),
+        trust,
+    )?;
     //TODO: check image type. It must be driver
     boot_services
         .start_image(nvme_image_handle)
@@ -78,6 +212,24 @@ fn load_nvme_driver(boot_services: &BootServices) -> Result<Handle> {
     Ok(nvme_image_handle)
 }
 
+// Load the NVMe driver, then any extra drivers the config asks for. Only the
+// NVMe driver is essential
+fn load_drivers(
+    boot_services: &BootServices,
+    secure_boot: &SecureBootState,
+    trust: Option<&TrustStore>,
+    config: &Config,
+) -> Result<Handle> {
+    let nvme_driver_handle = load_driver(boot_services, secure_boot, trust, config.driver_file())?;
+    for driver_file in config.extra_drivers.iter() {
+        match load_driver(boot_services, secure_boot, trust, driver_file) {
+            Ok(_) => info!("Loaded extra driver {}", driver_file),
+            Err(e) => warn!("Failed to load extra driver {}: {:?}", driver_file, e),
+        }
+    }
+    Ok(nvme_driver_handle)
+}
+
 // Get DevicePath string for the handle
 fn get_device_path_cstr16(boot_services: &BootServices, handle: Handle) -> Result<CString16> {
     boot_services
@@ -96,22 +248,69 @@ fn get_device_path_boxed(bs: &BootServices, handle: Handle) -> Result<Box<Device
         .map(|dpp| dpp.to_boxed())
 }
 
+// Open protocol without taking the device away from the drivers bound to it
+fn open_protocol_shared<P: ProtocolPointer + ?Sized>(
+    bs: &BootServices,
+    handle: Handle,
+) -> Result<ScopedProtocol<'_, P>> {
+    // Safety: handles opened this way belong to firmware drivers and are not
+    // uninstalled while jumpstart is running
+    unsafe {
+        bs.open_protocol::<P>(
+            OpenProtocolParams {
+                handle,
+                agent: bs.image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+    .map_err(anyhow::Error::msg)
+}
+
+// Connect just the controllers along `device_path`, the same way the firmware
+// boot manager does, and return the handle for the complete path
+fn connect_device_path(bs: &BootServices, device_path: &DevicePath) -> Result<Handle> {
+    let mut previous = None;
+    loop {
+        let mut remaining = device_path;
+        let handle = bs
+            .locate_device_path::<DevicePath>(&mut remaining)
+            .map_err(anyhow::Error::msg)?;
+        if remaining.node_iter().next().is_none() {
+            return Ok(handle);
+        }
+        if previous == Some(handle) {
+            return Err(anyhow!("no driver produced the rest of the device path"));
+        }
+        previous = Some(handle);
+        bs.connect_controller(handle, None, Some(remaining), false)
+            .map_err(anyhow::Error::msg)
+            .context("failed to connect controller")?;
+    }
+}
+
 // Connect all handles to a driver
 fn connect_all_handles_to_driver(
     boot_services: &BootServices,
     driver_handle: Handle,
+    strategy: ConnectStrategy,
 ) -> Result<Vec<Handle>> {
     let mut connected_handles = Vec::new();
 
-    info!("Connecting all handles to NVME driver");
+    info!("Connecting all handles to NVME driver ({:?})", strategy);
     let handles = boot_services
         .locate_handle_buffer(SearchType::AllHandles)
         .map_err(anyhow::Error::msg)?;
 
+    // TODO: why Some(driver_handle) causes CPU exception?
+    // it doesn't happen on QEMU. Buggy Dell firmware? Hence opt-in per model
+    let driver = match strategy {
+        ConnectStrategy::All => None,
+        ConnectStrategy::Driver => Some(driver_handle),
+    };
     for handle in handles.iter() {
-        // TODO: why Some(driver_handle) causes CPU exception?
-        // it doesn't happen on QEMU. Buggy Dell firmware?
-        match boot_services.connect_controller(*handle, None, None, true) {
+        match boot_services.connect_controller(*handle, driver, None, true) {
             Ok(_) => {
                 info!("Connected Handle: {:?}", handle);
                 connected_handles.push(*handle);
@@ -128,20 +327,34 @@ fn connect_all_handles_to_driver(
     Ok(connected_handles)
 }
 
-fn get_nvme_fs_device_paths(bs: &BootServices) -> Result<Vec<Box<DevicePath>>> {
-    let all_paths = get_all_device_paths_for_protocol::<SimpleFileSystem>(bs)?;
-    let nvme_paths = all_paths
-        .iter()
-        .filter(|p| p.is_nvme())
-        .map(|p| p.to_boxed())
-        .collect();
-    Ok(nvme_paths)
-}
-
 fn get_all_block_device_paths(bs: &BootServices) -> Result<Vec<Box<DevicePath>>> {
     get_all_device_paths_for_protocol::<BlockIO>(bs)
 }
 
+// Read GPT of every whole NVMe disk. Disks without a valid GPT are skipped
+fn get_nvme_gpt_disks(bs: &BootServices) -> Result<Vec<(Box<DevicePath>, GptDisk)>> {
+    let mut disks = Vec::new();
+    for handle in get_all_handles_for_protocol(bs, &BlockIO::GUID)? {
+        let Ok(path) = get_device_path_boxed(bs, handle) else {
+            continue;
+        };
+        if !path.is_nvme() {
+            continue;
+        }
+        let Ok(device) = BlockDevice::open(bs, handle) else {
+            continue;
+        };
+        if !device.is_whole_disk() {
+            continue;
+        }
+        match GptDisk::read(&device) {
+            Ok(gpt) => disks.push((path, gpt)),
+            Err(e) => info!("Skipping NVMe disk without GPT: {:?}", e),
+        }
+    }
+    Ok(disks)
+}
+
 fn get_all_disk_device_paths(bs: &BootServices) -> Result<Vec<Box<DevicePath>>> {
     get_all_device_paths_for_protocol::<DiskIo>(bs)
 }
@@ -167,111 +380,622 @@ fn get_all_handles_for_protocol(bs: &BootServices, protocol: &Guid) -> Result<Ve
         .collect())
 }
 
-trait DevicePathExt {
-    fn file_path(&self) -> Option<&proto::device_path::media::FilePath>;
-    fn hard_drive(&self) -> Option<&HardDrive>;
-    fn is_nvme(&self) -> bool;
+trait AsBuildNode {
+    fn as_media_file_path(&self) -> Result<CString16>;
 }
 
-impl DevicePathExt for DevicePath {
-    fn file_path(&self) -> Option<&proto::device_path::media::FilePath> {
-        for inst in self.instance_iter() {
-            for node in inst.node_iter() {
-                if node.full_type() == (DeviceType::MEDIA, DeviceSubType::MEDIA_FILE_PATH) {
-                    let e_node = node.as_enum().unwrap();
-                    if let DevicePathNodeEnum::MediaFilePath(n) = e_node {
-                        return Some(n);
-                    }
-                }
+impl AsBuildNode for DevicePathNodeEnum<'_> {
+    fn as_media_file_path(&self) -> Result<CString16> {
+        match self {
+            DevicePathNodeEnum::MediaFilePath(p) => {
+                let cstr16 = CString16::try_from(&p.path_name()).map_err(anyhow::Error::msg)?;
+                Ok(cstr16)
             }
+            _ => Err(anyhow!("not a MediaFilePath")),
         }
+    }
+}
+
+impl AsBuildNode for &DevicePathNodeEnum<'_> {
+    fn as_media_file_path(&self) -> Result<CString16> {
+        (*self).as_media_file_path()
+    }
+}
+
+impl AsBuildNode for DevicePathNode {
+    fn as_media_file_path(&self) -> Result<CString16> {
+        // FIXME: we should convert an error here to anyhow::Error
+        // but NodeConversionError doesn't implement Display trait
+        self.as_enum().unwrap().as_media_file_path()
+    }
+}
+
+// Filesystem and file path of a full device path: a filesystem followed by file path nodes
+fn locate_file(bs: &BootServices, device_path: &DevicePath) -> Result<(Handle, CString16)> {
+    let mut remaining = device_path;
+    let fs_handle = bs
+        .locate_device_path::<SimpleFileSystem>(&mut remaining)
+        .map_err(anyhow::Error::msg)?;
+    let path =
+        CString16::try_from(file_path_string(remaining)?.as_str()).map_err(anyhow::Error::msg)?;
+    Ok((fs_handle, path))
+}
+
+// Read an image and, if asked for, its detached signature next to it
+fn read_image(
+    bs: &BootServices,
+    device_path: &DevicePath,
+    with_signature: bool,
+) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    let (fs_handle, path) = locate_file(bs, device_path)?;
+    let mut fs = FileSystem::new(open_protocol_shared::<SimpleFileSystem>(bs, fs_handle)?);
+    let data = fs.read(Path::new(&path)).map_err(anyhow::Error::msg)?;
+    if !with_signature {
+        return Ok((data, None));
+    }
+    let signature_path = CString16::try_from(format!("{}{}", path, SIGNATURE_SUFFIX).as_str())
+        .map_err(anyhow::Error::msg)?;
+    let signature_path = Path::new(&signature_path);
+    let signature = if fs.try_exists(signature_path).map_err(anyhow::Error::msg)? {
+        Some(fs.read(signature_path).map_err(anyhow::Error::msg)?)
+    } else {
         None
+    };
+    Ok((data, signature))
+}
+
+// Load an image without starting it. The image is read into memory so we
+// see exactly the bytes that run: they are hashed and logged, checked against
+// our trust list and, when shim started us, by shim, the same way grub does
+// it as shim's second stage, and measured into the TPM. `device_path` is
+// passed along so LoadedImage::file_path of the new image stays meaningful.
+// A path that ends at a partition, as Boot#### entries may, loads the
+// removable media loader there like the firmware's boot manager would
+fn load_image(
+    bs: &BootServices,
+    device_path: &DevicePath,
+    name: &str,
+    trust: Option<&TrustStore>,
+) -> Result<Handle> {
+    let device_path = &*with_default_loader(device_path)?;
+    let (data, signature) = read_image(bs, device_path, trust.is_some())
+        .with_context(|| format!("failed to read {}", name))?;
+    load_image_from_buffer(bs, device_path, name, &data, signature.as_deref(), trust)
+}
+
+// Read and verify an image like load_image, without loading it. For the dry
+// run: the firmware measures whatever goes through LoadImage
+fn check_image(
+    bs: &BootServices,
+    device_path: &DevicePath,
+    trust: Option<&TrustStore>,
+) -> Result<()> {
+    let device_path = &*with_default_loader(device_path)?;
+    let name = device_path
+        .to_string(bs, DisplayOnly(false), AllowShortcuts(false))
+        .map_err(anyhow::Error::msg)?
+        .to_string();
+    let (data, signature) = read_image(bs, device_path, trust.is_some())
+        .with_context(|| format!("failed to read {}", name))?;
+    verify_image(bs, &name, &data, signature.as_deref(), trust)
+}
+
+// Hash and log an image read into memory and check it against our trust
+// list and shim
+fn verify_image(
+    bs: &BootServices,
+    name: &str,
+    data: &[u8],
+    signature: Option<&[u8]>,
+    trust: Option<&TrustStore>,
+) -> Result<()> {
+    let digest = sha256(data);
+    info!(
+        "{}: {} bytes, SHA-256 {}",
+        name,
+        data.len(),
+        to_hex(&digest)
+    );
+
+    if let Some(trust) = trust {
+        trust.verify(data, &digest, signature, name)?;
+    }
+    if let Some(shim_lock) = shim::get_shim_lock(bs) {
+        shim::verify(&shim_lock, data, name)?;
     }
+    Ok(())
+}
 
-    fn hard_drive(&self) -> Option<&HardDrive> {
-        for inst in self.instance_iter() {
-            for node in inst.node_iter() {
-                if node.full_type() == (DeviceType::MEDIA, DeviceSubType::MEDIA_HARD_DRIVE) {
-                    let e_node = node.as_enum().unwrap();
-                    if let DevicePathNodeEnum::MediaHardDrive(n) = e_node {
-                        return Some(n);
-                    }
-                }
+// Verify, measure and load an image that was already read into memory
+fn load_image_from_buffer(
+    bs: &BootServices,
+    device_path: &DevicePath,
+    name: &str,
+    data: &[u8],
+    signature: Option<&[u8]>,
+    trust: Option<&TrustStore>,
+) -> Result<Handle> {
+    verify_image(bs, name, data, signature, trust)?;
+    tpm::measure(bs, tpm::PCR_FILES, data, name)?;
+    bs.load_image(
+        bs.image_handle(),
+        LoadImageSource::FromBuffer {
+            buffer: data,
+            file_path: Some(device_path),
+        },
+    )
+    .map_err(|e| describe_load_error(e, name))
+}
+
+// Load an OS image without starting it
+fn load_image_from_device_path(
+    bs: &BootServices,
+    device_path: &DevicePath,
+    trust: Option<&TrustStore>,
+) -> Result<Handle> {
+    let device_path_str = device_path
+        .to_string(bs, DisplayOnly(false), AllowShortcuts(false))
+        .map_err(anyhow::Error::msg)?;
+    info!("We'll load this image: {}", device_path_str);
+
+    // load the image
+    info!("Loading image....");
+    load_image(bs, device_path, &device_path_str.to_string(), trust)
+}
+
+// Start a loaded image, optionally passing it a command line.
+// Only returns if the image exited
+fn start_loaded_image(
+    bs: &BootServices,
+    image_handle: Handle,
+    load_options: Option<&CStr16>,
+) -> Result<()> {
+    if DRY_RUN.load(Ordering::Relaxed) {
+        info!("Dry run, not starting the image");
+        return bs.unload_image(image_handle).map_err(anyhow::Error::msg);
+    }
+    if let Some(options) = load_options {
+        info!("Load options: '{}'", options);
+        // measure exactly what the image gets: UCS-2 with the terminating null
+        tpm::measure(
+            bs,
+            tpm::PCR_CONFIG,
+            options.as_bytes(),
+            &format!("command line {}", options),
+        )?;
+        let mut loaded_image = bs
+            .open_protocol_exclusive::<LoadedImage>(image_handle)
+            .map_err(anyhow::Error::msg)?;
+        // Safety: `options` outlives the image's use of it, start_image
+        // doesn't return until the image exits
+        unsafe {
+            loaded_image.set_load_options(options.as_ptr().cast(), options.num_bytes() as u32);
+        }
+    }
+
+    // start the image
+    info!("Starting image....");
+    bs.start_image(image_handle).map_err(anyhow::Error::msg)
+}
+
+// Save the image we are about to start for the next boot's fast path.
+// Failing to do so only costs time, so errors are just logged
+fn remember_last_boot(
+    rs: &RuntimeServices,
+    device_path: &DevicePath,
+    source: &str,
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+) {
+    if let Err(e) = LastBoot::new(device_path, source, gpt_disks).and_then(|l| l.store(rs)) {
+        info!("Not caching boot path: {:?}", e);
+    }
+}
+
+// The cached path is only valid if whatever selected it last time would
+// select the same image now. For a Boot#### entry that depends on the
+// filesystems, so this must run once the cached path is connected
+fn is_last_boot_current(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    config: &Config,
+    last_boot: &LastBoot,
+) -> bool {
+    if config.slot_targets().is_some() || config.bls() {
+        // slot and BLS selection must run on every boot
+        return false;
+    }
+    if let Some(boot_target) = config.targets.first() {
+        return last_boot.source == boot_target.to_string();
+    }
+    let Some(index) = last_boot
+        .source
+        .strip_prefix("Boot")
+        .and_then(|i| usize::from_str_radix(i, 16).ok())
+    else {
+        return false;
+    };
+    let (Ok(boot_mgr), Ok(filesystems)) = (
+        EfiBootManager::new_from_variables(rs, LoadOptionType::Boot),
+        get_all_device_paths_for_protocol::<SimpleFileSystem>(bs),
+    ) else {
+        return false;
+    };
+    scan::is_cached_option_current(&boot_mgr, index, &last_boot.device_path, &filesystems)
+}
+
+// Connect only the controllers on the cached device path, check that the
+// partition is still on the same disk and still what would be selected, and
+// boot the cached image. Returns an error on any mismatch so the caller can
+// do the full scan
+fn boot_last_path(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    config: &Config,
+    last_boot: &LastBoot,
+    trust: Option<&TrustStore>,
+) -> Result<()> {
+    let stopwatch = Stopwatch::start();
+    let partition_path = truncate_device_path(
+        &last_boot.device_path,
+        (DeviceType::MEDIA, DeviceSubType::MEDIA_FILE_PATH),
+    )?;
+    connect_device_path(bs, &partition_path)?;
+
+    let disk_path = truncate_device_path(
+        &last_boot.device_path,
+        (DeviceType::MEDIA, DeviceSubType::MEDIA_HARD_DRIVE),
+    )?;
+    let mut remaining: &DevicePath = &disk_path;
+    let disk_handle = bs
+        .locate_device_path::<BlockIO>(&mut remaining)
+        .map_err(anyhow::Error::msg)?;
+    if remaining.node_iter().next().is_some() {
+        return Err(anyhow!("disk of the cached path is gone"));
+    }
+    let gpt = GptDisk::read(&BlockDevice::open(bs, disk_handle)?)?;
+    if gpt.header.disk_guid != last_boot.disk_guid
+        || gpt.find_by_unique_guid(&last_boot.partition_guid).is_none()
+    {
+        return Err(anyhow!("disk identity changed"));
+    }
+    if !is_last_boot_current(bs, rs, config, last_boot) {
+        return Err(anyhow!("{} would no longer be selected", last_boot.source));
+    }
+
+    let image_handle = load_image_from_device_path(bs, &last_boot.device_path, trust)?;
+    tpm::measure_selection(bs, &last_boot.source)?;
+    info!("Fast path took {} ms", stopwatch.elapsed_ms());
+    start_loaded_image(bs, image_handle, None)
+}
+
+// Boot services behind the Boot#### scan and the other candidates
+struct UefiFirmware<'a> {
+    bs: &'a BootServices,
+    rs: &'a RuntimeServices,
+    driver_handle: Handle,
+    connect: ConnectStrategy,
+    trust: Option<&'a TrustStore>,
+    // read once the driver is connected, the cached boot path refers to them
+    gpt_disks: Vec<(Box<DevicePath>, GptDisk)>,
+    // A/B slot state if slots are configured, updated as slots are tried
+    slot_state: RefCell<Option<SlotState>>,
+    stopwatch: Stopwatch,
+}
+
+impl UefiFirmware<'_> {
+    fn update_slot_state(&self, update: impl FnOnce(&mut SlotState)) -> Result<()> {
+        match self.slot_state.borrow_mut().as_mut() {
+            Some(state) => {
+                update(state);
+                state.store(self.rs)
             }
+            None => Ok(()),
         }
-        None
+    }
+}
+
+impl Firmware for UefiFirmware<'_> {
+    type Image = Handle;
+    type Selection = Selection;
+
+    fn connect_drivers(&self) -> Result<()> {
+        connect_all_handles_to_driver(self.bs, self.driver_handle, self.connect).map(|_| ())
+    }
+
+    fn filesystems(&self) -> Result<Vec<Box<DevicePath>>> {
+        get_all_device_paths_for_protocol::<SimpleFileSystem>(self.bs)
+    }
+
+    fn load_image(&self, candidate: &Candidate<Selection>) -> Result<Handle> {
+        let Selection::Slot(slot) = candidate.selection else {
+            return load_image_from_device_path(self.bs, &candidate.device_path, self.trust);
+        };
+        // a slot whose image is missing or doesn't verify keeps its tries,
+        // it is skipped until it loads again
+        let image_handle =
+            load_image_from_device_path(self.bs, &candidate.device_path, self.trust)?;
+        // the try must be accounted for before the image gets control
+        if let Err(e) = self.update_slot_state(|state| state.consume(slot)) {
+            if let Err(e) = self.bs.unload_image(image_handle) {
+                warn!("Failed to unload image: {:?}", e);
+            }
+            return Err(e);
+        }
+        Ok(image_handle)
     }
 
-    fn is_nvme(&self) -> bool {
-        for inst in self.instance_iter() {
-            for node in inst.node_iter() {
-                if node.full_type()
-                    == (
-                        DeviceType::MESSAGING,
-                        DeviceSubType::MESSAGING_NVME_NAMESPACE,
-                    )
-                {
-                    return true;
+    fn start_image(&self, image_handle: Handle, candidate: &Candidate<Selection>) -> Result<()> {
+        match &candidate.selection {
+            Selection::Slot(slot) => {
+                tpm::measure_selection(self.bs, &candidate.source)?;
+                let result = start_loaded_image(self.bs, image_handle, None);
+                if result.is_err() {
+                    self.update_slot_state(|state| state.mark_failed(*slot))?;
                 }
+                result
+            }
+            Selection::Bls { handle, entry } => {
+                // each attempt is counted on the ESP before the image is started
+                bls::count_boot_attempt(self.bs, *handle, entry)?;
+                bls::measure_initrds(self.bs, *handle, entry)?;
+                tpm::measure_selection(self.bs, &candidate.source)?;
+                let options = CString16::try_from(entry.load_options().as_str())
+                    .map_err(anyhow::Error::msg)?;
+                start_loaded_image(self.bs, image_handle, Some(&options))
+            }
+            Selection::CachedPath | Selection::Target | Selection::BootOption => {
+                tpm::measure_selection(self.bs, &candidate.source)?;
+                remember_last_boot(
+                    self.rs,
+                    &candidate.device_path,
+                    &candidate.source,
+                    &self.gpt_disks,
+                );
+                info!("Full scan took {} ms", self.stopwatch.elapsed_ms());
+                start_loaded_image(self.bs, image_handle, None)
             }
         }
-        false
     }
 }
 
-trait PartialEqExt {
-    fn eq(&self, other: &Self) -> bool;
+// Firmware stores short-form device paths starting at the HardDrive node.
+// Prepend the filesystem holding the same partition
+fn expand_device_path(bs: &BootServices, device_path: &DevicePath) -> Result<Box<DevicePath>> {
+    let mut remaining = device_path;
+    if bs
+        .locate_device_path::<SimpleFileSystem>(&mut remaining)
+        .is_ok()
+    {
+        return Ok(device_path.to_boxed());
+    }
+    let hd = device_path
+        .hard_drive()
+        .context("device path has neither a filesystem nor a HardDrive node")?;
+    for handle in get_all_handles_for_protocol(bs, &SimpleFileSystem::GUID)? {
+        let Ok(fs_path) = get_device_path_boxed(bs, handle) else {
+            continue;
+        };
+        if !fs_path.hard_drive().is_some_and(|h| h.eq(hd)) {
+            continue;
+        }
+        return match device_path.file_path() {
+            Some(file_path) => append_file_path(
+                &fs_path,
+                &file_path
+                    .path_name()
+                    .to_cstring16()
+                    .map_err(anyhow::Error::msg)?,
+            ),
+            None => Ok(fs_path),
+        };
+    }
+    Err(anyhow!(
+        "no filesystem has the partition of the HardDrive node"
+    ))
 }
 
-impl PartialEqExt for HardDrive {
-    fn eq(&self, other: &Self) -> bool {
-        self.partition_number() == other.partition_number()
-            && self.partition_start() == other.partition_start()
-            && self.partition_size() == other.partition_size()
-            && self.partition_format() == other.partition_format()
-            && self.partition_number() == other.partition_number()
-            && self.partition_signature() == other.partition_signature()
+// Run active SysPrep#### applications in SysPrepOrder, as the firmware boot
+// manager does before boot. A failing item is logged and skipped
+fn run_sysprep_options(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    trust: Option<&TrustStore>,
+) -> Result<()> {
+    let sysprep = EfiBootManager::new_from_variables(rs, LoadOptionType::SysPrep)?;
+    for (index, option) in sysprep.ordered_options() {
+        if !option.is_active() {
+            continue;
+        }
+        info!("Running SysPrep{:04X} '{}'", index, option.description);
+        let load_options = option.optional_data_as_cstring16();
+        let result = option
+            .device_path_list
+            .first()
+            .context("no device path")
+            .and_then(|path| expand_device_path(bs, path))
+            .and_then(|path| load_image_from_device_path(bs, &path, trust))
+            .and_then(|image_handle| start_loaded_image(bs, image_handle, load_options.as_deref()));
+        if let Err(e) = result {
+            info!("SysPrep{:04X} failed: {:?}", index, e);
+        }
     }
+    Ok(())
 }
 
-trait AsBuildNode {
-    fn as_media_file_path(&self) -> Result<CString16>;
+// Boot the Boot#### entry with the given index, as if the firmware boot
+// manager had picked it
+fn boot_option_by_index(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    index: usize,
+    trust: Option<&TrustStore>,
+) -> Result<()> {
+    let option = EfiLoadOption::new_from_variable(rs, LoadOptionType::Boot, index)?;
+    info!("Booting Boot{:04X} '{}'", index, option.description);
+    let load_options = option.optional_data_as_cstring16();
+    let device_path = option
+        .device_path_list
+        .first()
+        .context("no device path")
+        .and_then(|path| expand_device_path(bs, path))?;
+    let image_handle = load_image_from_device_path(bs, &device_path, trust)?;
+    tpm::measure_selection(bs, &format!("Boot{:04X}", index))?;
+    start_loaded_image(bs, image_handle, load_options.as_deref())
 }
 
-impl AsBuildNode for DevicePathNodeEnum<'_> {
-    fn as_media_file_path(&self) -> Result<CString16> {
-        match self {
-            DevicePathNodeEnum::MediaFilePath(p) => {
-                let cstr16 = CString16::try_from(&p.path_name()).map_err(anyhow::Error::msg)?;
-                Ok(cstr16)
-            }
-            _ => Err(anyhow!("not a MediaFilePath")),
+// Startup window: Key#### hotkeys boot their entry, F2 reboots into
+// firmware setup, Esc or a non-zero Timeout shows the boot menu
+fn handle_startup_keys(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    config: &Config,
+    driver_handle: Handle,
+    trust: Option<&TrustStore>,
+) -> Result<()> {
+    let key = match config.hotkey_timeout_ms() {
+        0 => None,
+        window_ms => Hotkeys::load(rs)?.wait(bs, window_ms)?,
+    };
+    let action = match key {
+        _ if config.menu => menu::show(bs, rs, menu::TIMEOUT_FOREVER)?,
+        Some(StartupKey::Hotkey(index)) => {
+            info!("Hotkey pressed for Boot{:04X}", index);
+            MenuAction::Boot(index as usize)
         }
+        Some(StartupKey::FirmwareSetup) => MenuAction::Power(PowerAction::FirmwareSetup),
+        // the user asked for the menu, don't count down
+        Some(StartupKey::Menu) => menu::show(bs, rs, menu::TIMEOUT_FOREVER)?,
+        None => match menu::timeout(rs, config) {
+            0 => MenuAction::Continue,
+            timeout => menu::show(bs, rs, timeout)?,
+        },
+    };
+    match action {
+        MenuAction::Continue => Ok(()),
+        MenuAction::Boot(index) => {
+            // the entry may well live on the NVMe disk
+            connect_all_handles_to_driver(bs, driver_handle, config.connect())?;
+            boot_option_by_index(bs, rs, index, trust)
+        }
+        MenuAction::Power(action) => power::run(action, rs),
     }
 }
 
-impl AsBuildNode for &DevicePathNodeEnum<'_> {
-    fn as_media_file_path(&self) -> Result<CString16> {
-        (*self).as_media_file_path()
+// Sync mode needs the NVMe driver to see the partitions Boot#### entries
+// point at
+fn run_sync(bs: &BootServices, rs: &RuntimeServices, config: &Config) -> Result<()> {
+    let trust = TrustStore::load(bs, config)?;
+    let secure_boot = SecureBootState::read(rs);
+    let driver_handle = load_drivers(bs, &secure_boot, trust.as_ref(), config)?;
+    connect_all_handles_to_driver(bs, driver_handle, config.connect())?;
+    sync::sync_companions(bs, rs, &get_nvme_gpt_disks(bs)?)
+}
+
+// Startup keys and the cached boot path, both before the full NVMe scan
+fn boot_early(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    config: &Config,
+    driver_handle: Handle,
+    trust: Option<&TrustStore>,
+) {
+    if let Err(e) = handle_startup_keys(bs, rs, config, driver_handle, trust) {
+        info!("Startup key handling failed: {:?}", e);
+    }
+    match LastBoot::load(rs) {
+        Ok(Some(last_boot)) => {
+            info!("Trying cached boot path from {}", last_boot.source);
+            match boot_last_path(bs, rs, config, &last_boot, trust) {
+                Ok(_) => info!("Image from cached boot path exited"),
+                Err(e) => info!("Cached boot path failed, doing full scan: {:?}", e),
+            }
+        }
+        Ok(None) => info!("No cached boot path"),
+        Err(e) => info!("Failed to read cached boot path: {:?}", e),
     }
 }
 
-impl AsBuildNode for DevicePathNode {
-    fn as_media_file_path(&self) -> Result<CString16> {
-        // FIXME: we should convert an error here to anyhow::Error
-        // but NodeConversionError doesn't implement Display trait
-        self.as_enum().unwrap().as_media_file_path()
+// The inventory should include what the NVMe driver adds, but is still
+// worth having when the driver doesn't load
+fn run_inventory(bs: &BootServices, rs: &RuntimeServices, config: &Config) -> Result<()> {
+    let trust = TrustStore::load(bs, config)?;
+    let secure_boot = SecureBootState::read(rs);
+    match load_drivers(bs, &secure_boot, trust.as_ref(), config) {
+        Ok(driver_handle) => {
+            connect_all_handles_to_driver(bs, driver_handle, config.connect())?;
+        }
+        Err(e) => warn!("Taking inventory without the NVMe driver: {:?}", e),
     }
+    inventory::run(bs, rs, config)
 }
 
 fn run_jumpstarter(bs: &BootServices, rs: &RuntimeServices) -> Result<()> {
-    let nvme_driver_handle = load_nvme_driver(bs)?;
+    let system = smbios::system_info();
+    if let Some(system) = &system {
+        system.log();
+    }
+    let config = config::load(bs, rs, system.as_ref())?;
+    if let Some(level) = config.log_level {
+        log::set_max_level(level);
+    }
+    DRY_RUN.store(config.dry_run, Ordering::Relaxed);
+    match config.mode() {
+        Mode::Install => return install::install_driver(bs, rs, config.driver_file()),
+        Mode::Uninstall => return install::uninstall_driver(bs, rs),
+        Mode::Sync => return run_sync(bs, rs, &config),
+        Mode::Unsync => return sync::remove_companions(rs),
+        Mode::Inventory => return run_inventory(bs, rs, &config),
+        Mode::Boot => {}
+    }
+    if let Some(action) = config.power_action {
+        return power::run(action, rs);
+    }
 
-    let _connected_handles = connect_all_handles_to_driver(bs, nvme_driver_handle)?;
+    // getting back here means nothing booted, or everything exited
+    let result = boot(bs, rs, &config);
+    let Some(action) = config.final_action else {
+        return result;
+    };
+    if let Err(e) = &result {
+        info!("Boot failed: {:?}", e);
+    }
+    power::run(action, rs)
+}
+
+fn boot(bs: &BootServices, rs: &RuntimeServices, config: &Config) -> Result<()> {
+    let secure_boot = SecureBootState::read(rs);
+    secure_boot.log();
+    if shim::get_shim_lock(bs).is_some() {
+        info!("Started by shim, images will be verified with the shim lock protocol");
+    }
 
+    tpm::log_status(bs);
+
+    let trust = TrustStore::load(bs, config)?;
+    let trust = trust.as_ref();
+
+    let nvme_driver_handle = load_drivers(bs, &secure_boot, trust, config)?;
+    timing::calibrate(bs);
+    // a dry run must not start anything but the drivers it needs to see the
+    // disks, the report covers the rest
+    if !config.dry_run {
+        run_sysprep_options(bs, rs, trust)?;
+        boot_early(bs, rs, config, nvme_driver_handle, trust);
+    }
+
+    let mut firmware = UefiFirmware {
+        bs,
+        rs,
+        driver_handle: nvme_driver_handle,
+        connect: config.connect(),
+        trust,
+        gpt_disks: Vec::new(),
+        slot_state: RefCell::new(None),
+        stopwatch: Stopwatch::start(),
+    };
     // after connecting all handles to the driver, we should be able to get a simple filesystem
     // for the NVMe device
-    let fs_device_paths = get_nvme_fs_device_paths(bs)?;
+    let fs_device_paths = scan::discover_nvme_filesystems(&firmware)?;
     for path in fs_device_paths.iter() {
         info!(
             "FS Device Path: {}",
@@ -280,120 +1004,45 @@ fn run_jumpstarter(bs: &BootServices, rs: &RuntimeServices) -> Result<()> {
         );
         info!("HardDrive: {:?}", path.hard_drive());
         info!("FilePath: {:?}", path.file_path());
-        info!("Is NVMe: {}", path.is_nvme());
-    }
-
-    let boot_mgr = EfiBootManager::new_from_variables(rs)?;
-
-    for (index, boot_option) in boot_mgr.boot_options.iter() {
-        info!("Boot{:04X}:", index);
-        for (i, p) in boot_option.device_path_list.iter().enumerate() {
-            let s = p
-                .to_string(bs, DisplayOnly(false), AllowShortcuts(false))
-                .map_err(anyhow::Error::msg)?;
-            info!("Segment {}: '{}'", i, s);
-            // get HardDrive from the device path
-            if let Some(hd) = p.hard_drive() {
-                info!("HardDrive: {:#?}", hd);
-                // compare the HardDrive with the HardDrive from NVMe device paths
-                for nvme_path in fs_device_paths.iter() {
-                    if let Some(nvme_hd) = nvme_path.hard_drive() {
-                        if hd.eq(nvme_hd) {
-                            // info!("Matched NVMe Device Path: {}", s);
-                            // construct a new device path with the NVMe device path prepended
-                            let mut backing_vector: Vec<u8> = Vec::new();
-                            let mut new_device_path =
-                                DevicePathBuilder::with_vec(&mut backing_vector);
-                            // push the NVMe device path
-                            for node in nvme_path.node_iter() {
-                                new_device_path = new_device_path.push(&node).unwrap();
-                            }
-                            // get the file path from the boot option
-                            if let Some(file_path) = p
-                                .node_iter()
-                                .find(|n| {
-                                    n.full_type()
-                                        == (DeviceType::MEDIA, DeviceSubType::MEDIA_FILE_PATH)
-                                })
-                                .and_then(|e| e.as_media_file_path().ok())
-                            {
-                                new_device_path = new_device_path
-                                    .push(&build::media::FilePath {
-                                        path_name: &file_path,
-                                    })
-                                    .map_err(anyhow::Error::msg)?;
-                            }
-                            let new_device_path =
-                                new_device_path.finalize().map_err(anyhow::Error::msg)?;
-
-                            info!(
-                                "We'll load this image: {}",
-                                new_device_path
-                                    .to_string(bs, DisplayOnly(false), AllowShortcuts(false))
-                                    .map_err(anyhow::Error::msg)?
-                            );
-
-                            // load the image
-                            info!("Loading image....");
-                            let image_handle = bs
-                                .load_image(
-                                    bs.image_handle(),
-                                    LoadImageSource::FromDevicePath {
-                                        device_path: &new_device_path,
-                                        from_boot_manager: true,
-                                    },
-                                )
-                                .map_err(anyhow::Error::msg)?;
-
-                            // start the image
-                            info!("Starting image....");
-                            bs.start_image(image_handle)
-                                .map_err(anyhow::Error::msg)
-                                .expect("Error starting image");
-                        }
-                    }
-                }
-            }
+    }
+
+    firmware.gpt_disks = get_nvme_gpt_disks(bs)?;
+    let gpt_disks = &firmware.gpt_disks;
+    for (path, gpt) in gpt_disks.iter() {
+        info!(
+            "NVMe disk: {}",
+            path.to_string(bs, DisplayOnly(false), AllowShortcuts(false))
+                .map_err(anyhow::Error::msg)?
+        );
+        gpt.log();
+        for esp in gpt.esps().chain(gpt.xbootldrs()) {
+            info!("Boot partition #{} '{}'", esp.number, esp.name);
         }
     }
 
-    // info!("BootOrder: {:?}", boot_mgr.boot_order);
-    // info!(
-    //     "Next available boot index: {}",
-    //     boot_mgr.get_next_available_boot_index()?
-    // );
-
-    // // get_fs_device_paths(bs)?;
-
-    // // print all block device paths
-    // let block_device_paths = get_all_block_device_paths(bs)?;
-    // for path in block_device_paths.iter() {
-    //     info!(
-    //         "Block Device Path: {}",
-    //         path.to_string(&bs, DisplayOnly(false), AllowShortcuts(false))
-    //             .map_err(anyhow::Error::msg)?
-    //     );
-    // }
-
-    // // print all disk device paths
-    // let disk_device_paths = get_all_disk_device_paths(bs)?;
-    // for path in disk_device_paths.iter() {
-    //     info!(
-    //         "Disk Device Path: {}",
-    //         path.to_string(&bs, DisplayOnly(false), AllowShortcuts(false))
-    //             .map_err(anyhow::Error::msg)?
-    //     );
-    // }
-
-    // [ INFO]:  src/main.rs@358: Next available boot index: 5
-    // [ INFO]:  src/main.rs@368: Block Device Path: PciRoot(0x0)/Pci(0x1,0x0)/Pci(0x0,0x0)/Ctrl(0x0)/Scsi(0x0,0x0)
-    // [ INFO]:  src/main.rs@368: Block Device Path: PciRoot(0x0)/Pci(0x1A,0x0)/USB(0x0,0x0)/USB(0x5,0x0)/USB(0x3,0x0)/Unit(0x0)
-    // [ INFO]:  src/main.rs@368: Block Device Path: PciRoot(0x0)/Pci(0x1A,0x0)/USB(0x0,0x0)/USB(0x5,0x0)/USB(0x3,0x0)/Unit(0x1)
-    // [ INFO]:  src/main.rs@368: Block Device Path: PciRoot(0x0)/Pci(0x2,0x0)/Pci(0x0,0x0)/NVMe(0x1,0E-00-00-B0-81-A7-79-64)
-    // [ INFO]:  src/main.rs@378: Disk Device Path: PciRoot(0x0)/Pci(0x1,0x0)/Pci(0x0,0x0)/Ctrl(0x0)/Scsi(0x0,0x0)
-    // [ INFO]:  src/main.rs@378: Disk Device Path: PciRoot(0x0)/Pci(0x1A,0x0)/USB(0x0,0x0)/USB(0x5,0x0)/USB(0x3,0x0)/Unit(0x0)
-    // [ INFO]:  src/main.rs@378: Disk Device Path: PciRoot(0x0)/Pci(0x1A,0x0)/USB(0x0,0x0)/USB(0x5,0x0)/USB(0x3,0x0)/Unit(0x1)
-    // [ INFO]:  src/main.rs@378: Disk Device Path: PciRoot(0x0)/Pci(0x2,0x0)/Pci(0x0,0x0)/NVMe(0x1,0E-00-00-B0-81-A7-79-64)
+    if config.dry_run {
+        return explain::run(bs, rs, config, &fs_device_paths, gpt_disks, trust);
+    }
+
+    if config.sync {
+        if let Err(e) = sync::sync_companions(bs, rs, gpt_disks) {
+            warn!("Failed to sync companion entries: {:?}", e);
+        }
+    }
+
+    let slot_state = selection::load_slot_state(rs, config)?;
+    let candidates = selection::candidates(
+        bs,
+        rs,
+        config,
+        &fs_device_paths,
+        gpt_disks,
+        slot_state.as_ref(),
+        &mut |line| info!("{}", line),
+    )?;
+
+    firmware.slot_state = RefCell::new(slot_state);
+    scan::boot_candidates(&firmware, &candidates);
 
     Ok(())
 }
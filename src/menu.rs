@@ -0,0 +1,149 @@
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use anyhow::Result;
+use bootmgr::{
+    boot_vars::{EfiBootManager, LoadOptionType},
+    config::{Config, PowerAction},
+    global_vars::read_timeout,
+};
+use log::warn;
+use uefi::table::{boot::BootServices, runtime::RuntimeServices};
+use uefi_services::{print, println};
+
+use crate::{
+    hotkeys::{open_text_input_ex, CHAR_CARRIAGE_RETURN, SCAN_DOWN, SCAN_ESC, SCAN_F2, SCAN_UP},
+    power, sync,
+};
+
+// Timeout value meaning "wait until the user picks something"
+pub const TIMEOUT_FOREVER: u16 = 0xffff;
+
+// Polling interval while the menu is shown
+const TICKS_PER_SECOND: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    // go on with the automatic boot
+    Continue,
+    // boot a Boot#### entry
+    Boot(usize),
+    Power(PowerAction),
+}
+
+struct MenuItem {
+    label: String,
+    action: MenuAction,
+}
+
+// Menu countdown in seconds: the config wins over the firmware's Timeout
+pub fn timeout(rs: &RuntimeServices, config: &Config) -> u16 {
+    if let Some(timeout) = config.timeout {
+        return timeout;
+    }
+    match read_timeout(rs) {
+        Ok(timeout) => timeout.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to read Timeout: {:?}", e);
+            0
+        }
+    }
+}
+
+fn menu_items(rs: &RuntimeServices, firmware_ui: bool) -> Result<Vec<MenuItem>> {
+    let mut items = Vec::new();
+    items.push(MenuItem {
+        label: "Continue automatic boot".into(),
+        action: MenuAction::Continue,
+    });
+    let boot_mgr = EfiBootManager::new_from_variables(rs, LoadOptionType::Boot)?;
+    for (index, option) in boot_mgr.ordered_options() {
+        // companions would only list their targets twice
+        if option.is_active() && !sync::is_companion(option) {
+            items.push(MenuItem {
+                label: format!("Boot{:04X} {}", index, option.description),
+                action: MenuAction::Boot(index),
+            });
+        }
+    }
+    if firmware_ui {
+        items.push(MenuItem {
+            label: "Firmware setup".into(),
+            action: MenuAction::Power(PowerAction::FirmwareSetup),
+        });
+    }
+    items.push(MenuItem {
+        label: "Reboot".into(),
+        action: MenuAction::Power(PowerAction::Reboot),
+    });
+    items.push(MenuItem {
+        label: "Shut down".into(),
+        action: MenuAction::Power(PowerAction::Shutdown),
+    });
+    Ok(items)
+}
+
+fn draw(items: &[MenuItem], selected: usize) {
+    let mut st = uefi_services::system_table();
+    let _ = st.stdout().clear();
+    println!("jumpstart boot menu");
+    println!();
+    for (i, item) in items.iter().enumerate() {
+        let marker = if i == selected { '>' } else { ' ' };
+        println!("{} {}. {}", marker, i + 1, item.label);
+    }
+    println!();
+    println!("Up/Down and Enter or 1-9 to select, F2 for firmware setup, Esc to continue");
+    println!("R reboots and boots the selected Boot#### entry once");
+}
+
+// Show the menu until an item is picked. Counts down `timeout` seconds
+// unless it is TIMEOUT_FOREVER, any key stops the countdown
+pub fn show(bs: &BootServices, rs: &RuntimeServices, timeout: u16) -> Result<MenuAction> {
+    let firmware_ui = power::firmware_ui_supported(rs);
+    let items = menu_items(rs, firmware_ui)?;
+    let mut input = open_text_input_ex(bs)?;
+    let mut selected = 0;
+    let mut remaining = (timeout != TIMEOUT_FOREVER).then_some(timeout as u32 * TICKS_PER_SECOND);
+    draw(&items, selected);
+    loop {
+        if let Some(ticks) = remaining {
+            if ticks == 0 {
+                return Ok(MenuAction::Continue);
+            }
+            if ticks % TICKS_PER_SECOND == 0 {
+                print!("\rContinuing in {} s ", ticks / TICKS_PER_SECOND);
+            }
+            remaining = Some(ticks - 1);
+        }
+        while let Some(key_data) = input.read_key_stroke().map_err(anyhow::Error::msg)? {
+            remaining = None;
+            let key = key_data.key;
+            match (key.scan_code, key.unicode_char) {
+                (SCAN_UP, _) => selected = selected.checked_sub(1).unwrap_or(items.len() - 1),
+                (SCAN_DOWN, _) => selected = (selected + 1) % items.len(),
+                (SCAN_F2, _) if firmware_ui => {
+                    return Ok(MenuAction::Power(PowerAction::FirmwareSetup))
+                }
+                (SCAN_ESC, _) => return Ok(MenuAction::Continue),
+                (_, CHAR_CARRIAGE_RETURN) => return Ok(items[selected].action),
+                (_, c) if c == 'r' as u16 || c == 'R' as u16 => {
+                    if let MenuAction::Boot(index) = items[selected].action {
+                        return Ok(MenuAction::Power(PowerAction::RebootTo(index as u16)));
+                    }
+                }
+                (_, c) => {
+                    let digit = char::from_u32(c as u32).and_then(|c| c.to_digit(10));
+                    if let Some(item) = digit
+                        .filter(|d| *d > 0)
+                        .and_then(|d| items.get(d as usize - 1))
+                    {
+                        return Ok(item.action);
+                    }
+                }
+            }
+            draw(&items, selected);
+        }
+        bs.stall(1_000_000 / TICKS_PER_SECOND as usize);
+    }
+}
@@ -0,0 +1,108 @@
+extern crate alloc;
+
+use core::sync::atomic::Ordering;
+
+use alloc::format;
+use anyhow::{anyhow, Context, Result};
+use bootmgr::{
+    boot_vars::{EfiBootManager, LoadOptionType},
+    config::{BootTarget, PowerAction},
+    global_vars::{
+        read_boot_current, read_os_indications_supported, set_boot_next, set_os_indications,
+        EFI_OS_INDICATIONS_BOOT_TO_FW_UI,
+    },
+    jumpstart_vars::{encode_config_var, write_jumpstart_var, CONFIG_ONCE_VAR_NAME},
+};
+use log::{info, warn};
+use uefi::{
+    table::runtime::{ResetType, RuntimeServices},
+    Status,
+};
+
+use crate::DRY_RUN;
+
+// Only returns on error
+pub fn run(action: PowerAction, rs: &RuntimeServices) -> Result<()> {
+    if DRY_RUN.load(Ordering::Relaxed) {
+        info!("Dry run, skipping power action {}", action);
+        return Ok(());
+    }
+    info!("Power action: {}", action);
+    let reset_type = match action {
+        PowerAction::Reboot => ResetType::COLD,
+        PowerAction::WarmReboot => ResetType::WARM,
+        PowerAction::Shutdown => ResetType::SHUTDOWN,
+        PowerAction::FirmwareSetup => {
+            if !firmware_ui_supported(rs) {
+                return Err(anyhow!("firmware doesn't support booting to its setup UI"));
+            }
+            set_os_indications(rs, EFI_OS_INDICATIONS_BOOT_TO_FW_UI)?;
+            ResetType::COLD
+        }
+        PowerAction::RebootTo(index) => {
+            reboot_to(rs, index)?;
+            ResetType::COLD
+        }
+    };
+    rs.reset(reset_type, Status::SUCCESS, None)
+}
+
+// Reboot into Boot#### `index`. Entries on a GPT partition may only be
+// reachable through jumpstart's NVMe driver, so their target is left for
+// the next boot and BootNext starts jumpstart; bls is turned off so the
+// target isn't preempted. Anything else (network, USB, firmware apps) is
+// started by the firmware through BootNext
+fn reboot_to(rs: &RuntimeServices, index: u16) -> Result<()> {
+    let boot_mgr = EfiBootManager::new_from_variables(rs, LoadOptionType::Boot)?;
+    let option = boot_mgr
+        .option(index as usize)
+        .with_context(|| format!("no Boot{:04X} entry", index))?;
+    let Some(target) = BootTarget::from_load_option(option) else {
+        info!("Next boot: Boot{:04X}", index);
+        return set_boot_next(rs, index);
+    };
+    let text = format!("target={}\nbls=off\n", target);
+    write_jumpstart_var(rs, CONFIG_ONCE_VAR_NAME, &encode_config_var(&text))?;
+    info!("Next boot: {}", target);
+
+    match read_boot_current(rs)? {
+        Some(current) => set_boot_next(rs, own_boot_entry(&boot_mgr, current))?,
+        // without BootCurrent, BootOrder will have to bring us back
+        None => warn!("BootCurrent not set, not setting BootNext"),
+    }
+    Ok(())
+}
+
+// Boot#### entry that starts jumpstart without load options. The current one
+// may be a companion whose `target=` would override JumpstartConfigOnce
+fn own_boot_entry(boot_mgr: &EfiBootManager, current: u16) -> u16 {
+    let Some(current_option) = boot_mgr.option(current as usize) else {
+        return current;
+    };
+    if current_option.optional_data.is_none() {
+        return current;
+    }
+    boot_mgr
+        .ordered_options()
+        .into_iter()
+        .find(|(_, option)| {
+            option.optional_data.is_none()
+                && option.device_path_list.len() == current_option.device_path_list.len()
+                && option
+                    .device_path_list
+                    .iter()
+                    .zip(current_option.device_path_list.iter())
+                    .all(|(a, b)| a.as_bytes() == b.as_bytes())
+        })
+        .map_or(current, |(index, _)| index as u16)
+}
+
+pub fn firmware_ui_supported(rs: &RuntimeServices) -> bool {
+    match read_os_indications_supported(rs) {
+        Ok(supported) => supported & EFI_OS_INDICATIONS_BOOT_TO_FW_UI != 0,
+        Err(e) => {
+            warn!("Failed to read OsIndicationsSupported: {:?}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,123 @@
+extern crate alloc;
+
+use core::fmt::Display;
+
+use alloc::format;
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use uefi::{
+    cstr16,
+    table::runtime::{RuntimeServices, VariableVendor},
+    CStr16, Status,
+};
+
+// Secure Boot related global variables, see UEFI spec 3.3 "Globally Defined Variables"
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SecureBootState {
+    pub secure_boot: bool,
+    pub setup_mode: bool,
+    // AuditMode and DeployedMode only exist since UEFI 2.5
+    pub audit_mode: Option<bool>,
+    pub deployed_mode: Option<bool>,
+}
+
+fn read_bool_var(rs: &RuntimeServices, name: &CStr16) -> Option<bool> {
+    let mut buf = [0u8; 1];
+    rs.get_variable(name, &VariableVendor::GLOBAL_VARIABLE, &mut buf)
+        .ok()
+        .map(|(value, _)| value.first() == Some(&1))
+}
+
+impl SecureBootState {
+    pub fn read(rs: &RuntimeServices) -> Self {
+        SecureBootState {
+            secure_boot: read_bool_var(rs, cstr16!("SecureBoot")).unwrap_or(false),
+            setup_mode: read_bool_var(rs, cstr16!("SetupMode")).unwrap_or(false),
+            audit_mode: read_bool_var(rs, cstr16!("AuditMode")),
+            deployed_mode: read_bool_var(rs, cstr16!("DeployedMode")),
+        }
+    }
+
+    // Firmware verifies every image it loads and refuses unverified ones
+    pub fn is_enforcing(&self) -> bool {
+        self.secure_boot && !self.setup_mode && self.audit_mode != Some(true)
+    }
+
+    pub fn log(&self) {
+        info!("Secure Boot: {}", self);
+    }
+}
+
+impl Display for SecureBootState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} (SecureBoot: {}, SetupMode: {}, AuditMode: {:?}, DeployedMode: {:?})",
+            if self.is_enforcing() {
+                "enforcing"
+            } else {
+                "not enforcing"
+            },
+            self.secure_boot,
+            self.setup_mode,
+            self.audit_mode,
+            self.deployed_mode
+        )
+    }
+}
+
+// Turn a LoadImage failure into a message that says what went wrong and with
+// which file. The uefi::Error stays in the chain so main() can report its status
+pub fn describe_load_error(e: uefi::Error, image: &str) -> anyhow::Error {
+    let message = match e.status() {
+        Status::SECURITY_VIOLATION => {
+            "rejected by Secure Boot: the image is not signed by a key in db or is revoked by dbx"
+        }
+        Status::ACCESS_DENIED => "rejected by the firmware security policy",
+        Status::NOT_FOUND => "file not found",
+        Status::LOAD_ERROR | Status::UNSUPPORTED => "not a loadable image for this platform",
+        _ => "failed to load image",
+    };
+    anyhow::Error::msg(e).context(format!("{}: {}", image, message))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// True if the PE image carries an Authenticode signature, i.e. its
+// certificate table data directory is present and not empty
+pub fn pe_has_signature(data: &[u8]) -> Result<bool> {
+    const IMAGE_DIRECTORY_ENTRY_SECURITY: u32 = 4;
+
+    if data.get(0..2) != Some(b"MZ") {
+        return Err(anyhow!("not a PE image: missing MZ header"));
+    }
+    let pe_offset = u32_at(data, 0x3C).context("truncated DOS header")? as usize;
+    if data.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0") {
+        return Err(anyhow!("not a PE image: missing PE signature"));
+    }
+    // optional header follows the 4-byte signature and the 20-byte COFF header
+    let optional_header = pe_offset + 24;
+    let (count_offset, directories_offset) =
+        match u16_at(data, optional_header).context("truncated optional header")? {
+            0x10B => (optional_header + 92, optional_header + 96),
+            0x20B => (optional_header + 108, optional_header + 112),
+            magic => return Err(anyhow!("unknown PE optional header magic {:#x}", magic)),
+        };
+    let count = u32_at(data, count_offset).context("truncated optional header")?;
+    if count <= IMAGE_DIRECTORY_ENTRY_SECURITY {
+        return Ok(false);
+    }
+    let security_dir = directories_offset + IMAGE_DIRECTORY_ENTRY_SECURITY as usize * 8;
+    let size = u32_at(data, security_dir + 4).context("truncated data directories")?;
+    Ok(size > 0)
+}
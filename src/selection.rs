@@ -0,0 +1,198 @@
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::Result;
+use bootmgr::{
+    bls::{bootable_entries, sort_entries, BlsEntry},
+    boot_vars::{EfiBootManager, LoadOptionType},
+    config::Config,
+    device_path::append_file_path,
+    gpt::GptDisk,
+    scan::{self, Candidate},
+    slots::{Slot, SlotState},
+};
+use log::warn;
+use uefi::{
+    proto::device_path::DevicePath,
+    table::{boot::BootServices, runtime::RuntimeServices},
+    CString16, Handle,
+};
+
+use crate::{
+    bls,
+    target::{self, NvmePartition},
+};
+
+// What jumpstart tries once the NVMe disks are scanned, in order: A/B slots,
+// BLS entries, targets from the config, then Boot#### entries. The boot and
+// the dry run report both work from this list, so the report shows what the
+// boot would do.
+
+// How a candidate was selected
+pub enum Selection {
+    // the cached boot path, tried before the scan so never in the list
+    CachedPath,
+    Slot(Slot),
+    // a BLS entry on the partition with `handle`
+    Bls {
+        handle: Handle,
+        entry: Box<BlsEntry>,
+    },
+    Target,
+    BootOption,
+}
+
+// Slot state if A/B slots are configured
+pub fn load_slot_state(rs: &RuntimeServices, config: &Config) -> Result<Option<SlotState>> {
+    match config.slot_targets() {
+        Some(_) => SlotState::load(rs, config.slot_tries()).map(Some),
+        None => Ok(None),
+    }
+}
+
+// `note` gets a line for every decision, e.g. for the dry run report
+pub fn candidates(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    config: &Config,
+    fs_device_paths: &[Box<DevicePath>],
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+    slot_state: Option<&SlotState>,
+    note: &mut dyn FnMut(String),
+) -> Result<Vec<Candidate<Selection>>> {
+    let mut candidates = Vec::new();
+
+    match (config.slot_targets(), slot_state) {
+        (Some((slot_a, slot_b)), Some(state)) => {
+            for slot in state.try_order() {
+                let boot_target = match slot {
+                    Slot::A => slot_a,
+                    Slot::B => slot_b,
+                };
+                let info = state.slot(slot);
+                note(format!(
+                    "slot {} ({}): priority {}, {} tries left",
+                    slot, boot_target, info.priority, info.tries_remaining
+                ));
+                match target::resolve_target(bs, boot_target, gpt_disks) {
+                    Ok(device_path) => candidates.push(Candidate {
+                        source: format!("slot {} {}", slot, boot_target),
+                        device_path,
+                        selection: Selection::Slot(slot),
+                    }),
+                    Err(e) => note(format!("  not resolved: {}", e)),
+                }
+            }
+        }
+        _ => note("A/B slots: not configured".to_string()),
+    }
+
+    if config.bls() {
+        candidates.extend(bls_candidates(bs, gpt_disks, note)?);
+    }
+
+    // explicit targets from config/load options don't need any Boot#### entry
+    for boot_target in config.targets.iter() {
+        match target::resolve_target(bs, boot_target, gpt_disks) {
+            Ok(device_path) => candidates.push(Candidate {
+                source: boot_target.to_string(),
+                device_path,
+                selection: Selection::Target,
+            }),
+            Err(e) => note(format!("target {}: not resolved: {}", boot_target, e)),
+        }
+    }
+
+    // a broken BootOrder only costs the Boot#### candidates
+    match EfiBootManager::new_from_variables(rs, LoadOptionType::Boot) {
+        Ok(boot_mgr) => candidates.extend(scan::boot_option_candidates(
+            &boot_mgr,
+            fs_device_paths,
+            |_| Selection::BootOption,
+        )),
+        Err(e) => {
+            warn!("Failed to read Boot#### options: {:?}", e);
+            note(format!("Boot#### entries: unreadable: {}", e));
+        }
+    }
+    Ok(candidates)
+}
+
+// A BLS entry and the partition it was read from
+struct PartitionEntry<'a> {
+    partition: &'a NvmePartition,
+    entry: BlsEntry,
+}
+
+impl AsRef<BlsEntry> for PartitionEntry<'_> {
+    fn as_ref(&self) -> &BlsEntry {
+        &self.entry
+    }
+}
+
+// Boot Loader Spec entries found on NVMe ESP/XBOOTLDR partitions, best
+// first. Entries from all partitions are sorted together, so bad entries on
+// one partition don't go before good ones on another
+fn bls_candidates(
+    bs: &BootServices,
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+    note: &mut dyn FnMut(String),
+) -> Result<Vec<Candidate<Selection>>> {
+    let partitions = target::get_nvme_partitions(bs, gpt_disks)?;
+    let mut entries = Vec::new();
+    for partition in partitions
+        .iter()
+        .filter(|p| p.id.as_ref().is_some_and(|id| id.is_boot_partition()))
+    {
+        match bls::read_entries(bs, partition.handle) {
+            Ok(found) => entries.extend(
+                found
+                    .into_iter()
+                    .map(|entry| PartitionEntry { partition, entry }),
+            ),
+            Err(e) => note(format!("BLS entries: unreadable: {}", e)),
+        }
+    }
+    sort_entries(&mut entries);
+
+    let bootable: Vec<_> = bootable_entries(&entries).collect();
+    if bootable.len() < entries.len() {
+        note(format!(
+            "{} BLS entries with no tries left skipped",
+            entries.len() - bootable.len()
+        ));
+    }
+    let mut candidates = Vec::new();
+    for PartitionEntry { partition, entry } in bootable {
+        note(format!(
+            "BLS entry {}: '{}'{}",
+            entry.file_name,
+            entry.title(),
+            if entry.is_bad() {
+                ", no tries left"
+            } else {
+                ""
+            }
+        ));
+        match CString16::try_from(entry.image_path().as_str())
+            .map_err(anyhow::Error::msg)
+            .and_then(|path| append_file_path(&partition.device_path, &path))
+        {
+            Ok(device_path) => candidates.push(Candidate {
+                source: format!("BLS entry {}", entry.file_name),
+                device_path,
+                selection: Selection::Bls {
+                    handle: partition.handle,
+                    entry: Box::new(entry.clone()),
+                },
+            }),
+            Err(e) => note(format!("  invalid image path: {}", e)),
+        }
+    }
+    Ok(candidates)
+}
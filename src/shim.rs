@@ -0,0 +1,35 @@
+extern crate alloc;
+
+use alloc::format;
+use anyhow::Result;
+use log::info;
+use uefi::{
+    proto::shim::ShimLock,
+    table::boot::{BootServices, ScopedProtocol},
+    Status,
+};
+
+use crate::open_protocol_shared;
+
+// Shim installs its lock protocol before it starts the second stage
+// (grubx64.efi, which may be jumpstart). Returns None when we weren't
+// started by shim
+pub fn get_shim_lock(bs: &BootServices) -> Option<ScopedProtocol<'_, ShimLock>> {
+    let handle = bs.get_handle_for_protocol::<ShimLock>().ok()?;
+    open_protocol_shared::<ShimLock>(bs, handle).ok()
+}
+
+// Check an image against shim's vendor certificate, db and the MOK list
+pub fn verify(shim_lock: &ShimLock, data: &[u8], image: &str) -> Result<()> {
+    shim_lock.verify(data).map_err(|e| {
+        let message = match e.status() {
+            Status::SECURITY_VIOLATION | Status::ACCESS_DENIED => {
+                "rejected by shim: not signed by the vendor certificate, db or MOK, or revoked"
+            }
+            _ => "shim verification failed",
+        };
+        anyhow::Error::msg(e).context(format!("{}: {}", image, message))
+    })?;
+    info!("{}: verified by shim", image);
+    Ok(())
+}
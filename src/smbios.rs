@@ -0,0 +1,80 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use bootmgr::smbios::{structures, Structure, SystemInfo};
+use log::{info, warn};
+use uefi::{
+    table::cfg::{SMBIOS3_GUID, SMBIOS_GUID},
+    Guid,
+};
+
+// SMBIOS tables from the UEFI configuration table, DMTF DSP0134.
+// The 3.x entry point is preferred, it can describe tables above 4 GiB.
+const SMBIOS2_ANCHOR: &[u8] = b"_SM_";
+const SMBIOS3_ANCHOR: &[u8] = b"_SM3_";
+
+pub struct Smbios {
+    pub version: (u8, u8),
+    table: &'static [u8],
+}
+
+impl Smbios {
+    // Locate the structure table through the entry point in the
+    // configuration table. None if the firmware doesn't publish SMBIOS
+    pub fn find() -> Result<Option<Self>> {
+        let st = uefi_services::system_table();
+        let entry = |guid: Guid| {
+            st.config_table()
+                .iter()
+                .find(|e| e.guid == guid)
+                .map(|e| e.address as *const u8)
+        };
+        if let Some(entry_point) = entry(SMBIOS3_GUID) {
+            // Safety: firmware keeps the entry point and table mapped
+            let ep = unsafe { core::slice::from_raw_parts(entry_point, 0x18) };
+            if !ep.starts_with(SMBIOS3_ANCHOR) {
+                return Err(anyhow!("invalid SMBIOS 3 entry point"));
+            }
+            let size = u32::from_le_bytes(ep[0x0c..0x10].try_into().unwrap()) as usize;
+            let address = u64::from_le_bytes(ep[0x10..0x18].try_into().unwrap());
+            return Ok(Some(Smbios {
+                version: (ep[0x07], ep[0x08]),
+                table: unsafe { core::slice::from_raw_parts(address as *const u8, size) },
+            }));
+        }
+        if let Some(entry_point) = entry(SMBIOS_GUID) {
+            let ep = unsafe { core::slice::from_raw_parts(entry_point, 0x1f) };
+            if !ep.starts_with(SMBIOS2_ANCHOR) {
+                return Err(anyhow!("invalid SMBIOS 2 entry point"));
+            }
+            let size = u16::from_le_bytes([ep[0x16], ep[0x17]]) as usize;
+            let address = u32::from_le_bytes(ep[0x18..0x1c].try_into().unwrap());
+            return Ok(Some(Smbios {
+                version: (ep[0x06], ep[0x07]),
+                table: unsafe { core::slice::from_raw_parts(address as usize as *const u8, size) },
+            }));
+        }
+        Ok(None)
+    }
+
+    // All structures up to the end-of-table marker
+    pub fn structures(&self) -> Vec<Structure<'static>> {
+        structures(self.table)
+    }
+}
+
+// System identification, None if there is no usable SMBIOS
+pub fn system_info() -> Option<SystemInfo> {
+    match Smbios::find() {
+        Ok(Some(smbios)) => Some(SystemInfo::read(&smbios.structures())),
+        Ok(None) => {
+            info!("No SMBIOS tables");
+            None
+        }
+        Err(e) => {
+            warn!("Failed to read SMBIOS: {:?}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,165 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use anyhow::{anyhow, Result};
+use bootmgr::{
+    boot_vars::{
+        EfiBootManager, EfiLoadOption, LoadOptionAttributes, LoadOptionAttributesBits,
+        LoadOptionType,
+    },
+    config::{BootTarget, PartitionSelector},
+    gpt::GptDisk,
+    jumpstart_vars::{encode_load_options, is_companion_options, COMPANION_MARKER},
+};
+use log::{info, warn};
+use uefi::{
+    proto::device_path::{DevicePath, LoadedImageDevicePath},
+    table::{boot::BootServices, runtime::RuntimeServices},
+    CString16,
+};
+
+use crate::target;
+
+// Sync mode mirrors every Boot#### entry that points at an NVMe partition
+// with a companion entry the firmware can start without an NVMe driver: its
+// device path is jumpstart itself and its optional data is a `target=`
+// command line in the binary load options format, marked with
+// COMPANION_MARKER so operator entries with load options are left alone.
+const COMPANION_SUFFIX: &str = " (jumpstart)";
+
+pub fn is_companion(option: &EfiLoadOption) -> bool {
+    option
+        .optional_data
+        .as_deref()
+        .is_some_and(is_companion_options)
+}
+
+// Command line a companion passes to jumpstart
+fn companion_options(target: &BootTarget) -> String {
+    format!("{} target={}", COMPANION_MARKER, target)
+}
+
+// Partition GUID and path of a Boot#### entry on an NVMe partition
+fn nvme_target(option: &EfiLoadOption, nvme_uuids: &[uefi::Guid]) -> Option<BootTarget> {
+    BootTarget::from_load_option(option).filter(|target| {
+        matches!(&target.partition, PartitionSelector::Uuid(uuid) if nvme_uuids.contains(uuid))
+    })
+}
+
+fn own_device_path(bs: &BootServices) -> Result<Box<DevicePath>> {
+    let device_path = bs
+        .open_protocol_exclusive::<LoadedImageDevicePath>(bs.image_handle())
+        .map_err(anyhow::Error::msg)?;
+    Ok(device_path.to_boxed())
+}
+
+// Companions the current Boot#### entries call for, one per target
+fn wanted_companions(
+    bs: &BootServices,
+    boot_mgr: &EfiBootManager,
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+) -> Result<Vec<EfiLoadOption>> {
+    let nvme_uuids: Vec<_> = target::get_nvme_partitions(bs, gpt_disks)?
+        .into_iter()
+        .filter_map(|p| Some(p.id?.uuid))
+        .collect();
+    let jumpstart = own_device_path(bs)?;
+    let mut wanted: Vec<EfiLoadOption> = Vec::new();
+    for (index, option) in boot_mgr.ordered_options() {
+        if is_companion(option) || !option.is_active() {
+            continue;
+        }
+        let Some(target) = nvme_target(option, &nvme_uuids) else {
+            continue;
+        };
+        let optional_data = encode_load_options(&companion_options(&target));
+        if wanted
+            .iter()
+            .any(|c| c.optional_data.as_ref() == Some(&optional_data))
+        {
+            info!(
+                "Boot{:04X} duplicates an earlier entry for {}",
+                index, target
+            );
+            continue;
+        }
+        let description = format!("{}{}", option.description, COMPANION_SUFFIX);
+        wanted.push(EfiLoadOption {
+            attributes: LoadOptionAttributes::from(
+                LoadOptionAttributesBits::LoadOptionActive as u32,
+            ),
+            description: CString16::try_from(description.as_str())
+                .map_err(|_| anyhow!("invalid description '{}'", description))?,
+            device_path_list: alloc::vec![jumpstart.to_boxed()],
+            optional_data: Some(optional_data),
+        });
+    }
+    Ok(wanted)
+}
+
+// Create, update and remove companions so there is exactly one per NVMe
+// Boot#### target. Variables are only written when they change
+pub fn sync_companions(
+    bs: &BootServices,
+    rs: &RuntimeServices,
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+) -> Result<()> {
+    let mut boot_mgr = EfiBootManager::new_from_variables(rs, LoadOptionType::Boot)?;
+    let wanted = wanted_companions(bs, &boot_mgr, gpt_disks)?;
+
+    let mut existing: Vec<(usize, Option<Vec<u8>>)> = boot_mgr
+        .boot_options
+        .iter()
+        .filter(|(_, option)| is_companion(option))
+        .map(|(index, option)| (*index, option.optional_data.clone()))
+        .collect();
+    for companion in wanted {
+        let found = existing
+            .iter()
+            .position(|(_, data)| *data == companion.optional_data);
+        let index = match found {
+            Some(position) => {
+                let (index, _) = existing.remove(position);
+                let current = boot_mgr.option(index).map(Vec::<u8>::from);
+                if current.as_ref() != Some(&Vec::<u8>::from(&companion)) {
+                    info!("Updating Boot{:04X} '{}'", index, companion.description);
+                    boot_mgr.set_option(rs, index, companion)?;
+                }
+                index
+            }
+            None => {
+                let index = boot_mgr.get_next_available_boot_index()?;
+                info!("Adding Boot{:04X} '{}'", index, companion.description);
+                boot_mgr.set_option(rs, index, companion)?;
+                index
+            }
+        };
+        boot_mgr.add_to_order(rs, index, false)?;
+    }
+
+    // stale targets and duplicates
+    for (index, _) in existing {
+        info!("Removing Boot{:04X}", index);
+        boot_mgr.delete_option(rs, index)?;
+    }
+    Ok(())
+}
+
+// Remove all companions
+pub fn remove_companions(rs: &RuntimeServices) -> Result<()> {
+    let mut boot_mgr = EfiBootManager::new_from_variables(rs, LoadOptionType::Boot)?;
+    let companions: Vec<usize> = boot_mgr
+        .boot_options
+        .iter()
+        .filter(|(_, option)| is_companion(option))
+        .map(|(index, _)| *index)
+        .collect();
+    if companions.is_empty() {
+        warn!("No jumpstart companion entries found");
+    }
+    for index in companions {
+        boot_mgr.delete_option(rs, index)?;
+        info!("Removed Boot{:04X}", index);
+    }
+    Ok(())
+}
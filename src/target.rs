@@ -0,0 +1,96 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use anyhow::Result;
+use bootmgr::{
+    config::{BootTarget, PartitionId},
+    device_path::{append_file_path, DevicePathExt},
+    gpt::GptDisk,
+};
+use log::info;
+use uefi::{
+    proto::{
+        device_path::{media::PartitionSignature, DevicePath},
+        media::{fs::SimpleFileSystem, partition::PartitionInfo},
+    },
+    table::boot::BootServices,
+    Handle, Identify,
+};
+
+use crate::{get_all_handles_for_protocol, get_device_path_boxed, open_protocol_shared};
+
+// A filesystem on an NVMe partition
+pub struct NvmePartition {
+    pub handle: Handle,
+    pub device_path: Box<DevicePath>,
+    pub id: Option<PartitionId>,
+}
+
+// Ask the partition driver first, it knows the GPT entry the handle was made from
+fn partition_id_from_partition_info(bs: &BootServices, handle: Handle) -> Option<PartitionId> {
+    let info = open_protocol_shared::<PartitionInfo>(bs, handle).ok()?;
+    let entry = *info.gpt_partition_entry()?;
+    let name = entry.partition_name;
+    let units = name.iter().map(|c| u16::from(*c)).take_while(|c| *c != 0);
+    Some(PartitionId {
+        type_guid: entry.partition_type_guid.0,
+        uuid: entry.unique_partition_guid,
+        label: char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+    })
+}
+
+// Older firmware has no PartitionInfo, look the partition signature up in our own GPT copy
+fn partition_id_from_gpt(
+    path: &DevicePath,
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+) -> Option<PartitionId> {
+    let PartitionSignature::Guid(uuid) = path.hard_drive()?.partition_signature() else {
+        return None;
+    };
+    gpt_disks
+        .iter()
+        .find_map(|(_, gpt)| gpt.find_by_unique_guid(&uuid))
+        .map(|p| PartitionId {
+            type_guid: p.type_guid,
+            uuid,
+            label: p.name.clone(),
+        })
+}
+
+// All filesystems on NVMe partitions together with their GPT identity
+pub fn get_nvme_partitions(
+    bs: &BootServices,
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+) -> Result<Vec<NvmePartition>> {
+    let mut partitions = Vec::new();
+    for handle in get_all_handles_for_protocol(bs, &SimpleFileSystem::GUID)? {
+        let Ok(device_path) = get_device_path_boxed(bs, handle) else {
+            continue;
+        };
+        if !device_path.is_nvme() {
+            continue;
+        }
+        let id = partition_id_from_partition_info(bs, handle)
+            .or_else(|| partition_id_from_gpt(&device_path, gpt_disks));
+        partitions.push(NvmePartition {
+            handle,
+            device_path,
+            id,
+        });
+    }
+    Ok(partitions)
+}
+
+// Find the NVMe filesystem selected by the target and append the target's file path
+pub fn resolve_target(
+    bs: &BootServices,
+    target: &BootTarget,
+    gpt_disks: &[(Box<DevicePath>, GptDisk)],
+) -> Result<Box<DevicePath>> {
+    let partitions = get_nvme_partitions(bs, gpt_disks)?;
+    let partition = target.select(&partitions, |p| p.id.as_ref())?;
+    info!("Resolved {}", target);
+    append_file_path(&partition.device_path, &target.path_cstr16()?)
+}
@@ -0,0 +1,40 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(target_arch = "x86")]
+use core::arch::x86::_rdtsc;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::_rdtsc;
+
+use uefi::table::boot::BootServices;
+
+// Elapsed time based on the CPU timestamp counter. The firmware gives us no
+// monotonic clock, so the TSC rate is measured once against Stall()
+static TICKS_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+fn read_tsc() -> u64 {
+    // Safety: RDTSC is available on every CPU that can run UEFI firmware
+    unsafe { _rdtsc() }
+}
+
+pub fn calibrate(bs: &BootServices) {
+    let start = read_tsc();
+    bs.stall(10_000);
+    let ticks = read_tsc().wrapping_sub(start) / 10;
+    TICKS_PER_MS.store(ticks.max(1), Ordering::Relaxed);
+}
+
+pub struct Stopwatch(u64);
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Stopwatch(read_tsc())
+    }
+
+    // 0 if calibrate() was never called
+    pub fn elapsed_ms(&self) -> u64 {
+        match TICKS_PER_MS.load(Ordering::Relaxed) {
+            0 => 0,
+            ticks_per_ms => read_tsc().wrapping_sub(self.0) / ticks_per_ms,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+extern crate alloc;
+
+use core::mem::MaybeUninit;
+
+use alloc::{format, vec};
+use anyhow::{Context, Result};
+use log::info;
+use uefi::{
+    proto::tcg::{
+        v2::{HashLogExtendEventFlags, PcrEventInputs, Tcg},
+        EventType, PcrIndex,
+    },
+    table::boot::{BootServices, ScopedProtocol},
+};
+
+use crate::open_protocol_shared;
+
+// Measured boot through the TCG2 protocol. PCR use follows grub:
+//   PCR 8: config file, selected boot entry, command lines we pass
+//   PCR 9: contents of every file we load (drivers, images, initrds)
+// The firmware additionally measures PE images into PCR 4 in LoadImage.
+pub const PCR_CONFIG: PcrIndex = PcrIndex(8);
+pub const PCR_FILES: PcrIndex = PcrIndex(9);
+
+fn get_tcg(bs: &BootServices) -> Option<ScopedProtocol<'_, Tcg>> {
+    let handle = bs.get_handle_for_protocol::<Tcg>().ok()?;
+    let mut tcg = open_protocol_shared::<Tcg>(bs, handle).ok()?;
+    let capability = tcg.get_capability().ok()?;
+    capability.tpm_present().then_some(tcg)
+}
+
+pub fn is_present(bs: &BootServices) -> bool {
+    get_tcg(bs).is_some()
+}
+
+pub fn log_status(bs: &BootServices) {
+    if is_present(bs) {
+        info!(
+            "TPM 2.0 found, measuring into PCR {} and {}",
+            PCR_CONFIG.0, PCR_FILES.0
+        );
+    } else {
+        info!("No TPM 2.0 found, measured boot is disabled");
+    }
+}
+
+// Extend `pcr` with the hash of `data` and log an EV_IPL event described by
+// `description`. Does nothing without a TPM
+pub fn measure(bs: &BootServices, pcr: PcrIndex, data: &[u8], description: &str) -> Result<()> {
+    let Some(mut tcg) = get_tcg(bs) else {
+        return Ok(());
+    };
+    let mut buffer = vec![MaybeUninit::<u8>::uninit(); description.len() + 64];
+    let event =
+        PcrEventInputs::new_in_buffer(&mut buffer, pcr, EventType::IPL, description.as_bytes())
+            .map_err(anyhow::Error::msg)?;
+    tcg.hash_log_extend_event(HashLogExtendEventFlags::empty(), data, event)
+        .map_err(anyhow::Error::msg)
+        .with_context(|| format!("failed to measure {}", description))?;
+    info!("Measured {} into PCR {}", description, pcr.0);
+    Ok(())
+}
+
+// Record what was selected for boot; the description is the measured data
+pub fn measure_selection(bs: &BootServices, selection: &str) -> Result<()> {
+    measure(bs, PCR_CONFIG, selection.as_bytes(), selection)
+}
@@ -0,0 +1,157 @@
+extern crate alloc;
+
+use core::str::FromStr;
+
+use alloc::{format, vec::Vec};
+use anyhow::{anyhow, Context, Result};
+use bootmgr::{
+    config::Config,
+    crypto::{ed25519_verify, ED25519_PUBLIC_KEY_SIZE, ED25519_SIGNATURE_SIZE},
+};
+use log::info;
+use uefi::{
+    fs::{FileSystem, Path},
+    table::boot::BootServices,
+};
+
+use crate::{get_image_fs, jumpstart_path};
+
+// Allowlist of images jumpstart may start, independent of Secure Boot. An
+// image passes if its SHA-256 is listed, or if `<image>.sig` next to it holds
+// a detached Ed25519 signature (64 raw bytes) by one of the listed keys.
+//
+// Entries are built in from JUMPSTART_TRUST at compile time, or read from
+// `trust.list` in the jumpstart directory when nothing is built in, one per
+// line (or `;` separated):
+//   ed25519 <public key, 64 hex digits>
+//   sha256 <image digest, 64 hex digits>
+pub const TRUST_FILE_NAME: &str = "trust.list";
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+const BUILTIN_TRUST: Option<&str> = option_env!("JUMPSTART_TRUST");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustEntry {
+    Ed25519([u8; ED25519_PUBLIC_KEY_SIZE]),
+    Sha256([u8; 32]),
+}
+
+fn parse_hex32(s: &str) -> Result<[u8; 32]> {
+    if s.len() != 64 || !s.is_ascii() {
+        return Err(anyhow!("expected 64 hex digits, got '{}'", s));
+    }
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+            .map_err(|_| anyhow!("invalid hex '{}'", s))?;
+    }
+    Ok(bytes)
+}
+
+impl FromStr for TrustEntry {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, value) = s
+            .split_once(char::is_whitespace)
+            .with_context(|| format!("expected '<kind> <hex>', got '{}'", s))?;
+        match kind {
+            "ed25519" => Ok(TrustEntry::Ed25519(parse_hex32(value.trim())?)),
+            "sha256" => Ok(TrustEntry::Sha256(parse_hex32(value.trim())?)),
+            _ => Err(anyhow!("unknown trust entry kind '{}'", kind)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TrustStore {
+    pub entries: Vec<TrustEntry>,
+}
+
+impl TrustStore {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for line in text.split(['\n', ';']) {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            entries.push(line.parse()?);
+        }
+        Ok(TrustStore { entries })
+    }
+
+    // Built-in entries, or the ones on the ESP if nothing is built in.
+    // Returns None when images don't have to be verified: nothing is built
+    // in and the config doesn't ask for it. A build with embedded entries
+    // always verifies against those alone, so the check can't be turned off
+    // or widened by editing files on the ESP
+    pub fn load(bs: &BootServices, config: &Config) -> Result<Option<Self>> {
+        let builtin = TrustStore::parse(BUILTIN_TRUST.unwrap_or_default())
+            .context("invalid built-in trust entries")?;
+        if !builtin.entries.is_empty() {
+            info!(
+                "Image verification: {} built-in trust entries, {} is ignored",
+                builtin.entries.len(),
+                TRUST_FILE_NAME
+            );
+            return Ok(Some(builtin));
+        }
+        if !config.verify {
+            return Ok(None);
+        }
+
+        let mut fs = FileSystem::new(get_image_fs(bs)?);
+        let trust_path = jumpstart_path(bs, TRUST_FILE_NAME)?;
+        let path = Path::new(&trust_path);
+        let store = if fs.try_exists(path).map_err(anyhow::Error::msg)? {
+            let text = fs.read_to_string(path).map_err(anyhow::Error::msg)?;
+            TrustStore::parse(&text).with_context(|| format!("{}", trust_path))?
+        } else {
+            TrustStore::default()
+        };
+        if store.entries.is_empty() {
+            return Err(anyhow!(
+                "image verification is enabled but there are no trusted keys or digests"
+            ));
+        }
+        info!("Image verification: {} trust entries", store.entries.len());
+        Ok(Some(store))
+    }
+
+    // Check an image read into memory. `digest` is its SHA-256, `signature`
+    // the content of its .sig file
+    pub fn verify(
+        &self,
+        data: &[u8],
+        digest: &[u8; 32],
+        signature: Option<&[u8]>,
+        image: &str,
+    ) -> Result<()> {
+        if self.entries.contains(&TrustEntry::Sha256(*digest)) {
+            info!("{}: SHA-256 is allowlisted", image);
+            return Ok(());
+        }
+        if let Some(signature) = signature {
+            let signature: &[u8; ED25519_SIGNATURE_SIZE] = signature.try_into().map_err(|_| {
+                anyhow!(
+                    "{}{}: expected {} bytes, got {}",
+                    image,
+                    SIGNATURE_SUFFIX,
+                    ED25519_SIGNATURE_SIZE,
+                    signature.len()
+                )
+            })?;
+            let verified = self.entries.iter().any(|entry| match entry {
+                TrustEntry::Ed25519(key) => ed25519_verify(key, data, signature),
+                TrustEntry::Sha256(_) => false,
+            });
+            if verified {
+                info!("{}: Ed25519 signature verified", image);
+                return Ok(());
+            }
+        }
+        Err(anyhow!(
+            "{}: not trusted, its SHA-256 isn't allowlisted and it has no valid signature",
+            image
+        ))
+    }
+}